@@ -5,7 +5,6 @@ const $ = require("jquery");
 let MAX_INCLINE = Math.PI / 2 - 0.01, MIN_INCLINE = -MAX_INCLINE;
 let mouseDown = false;
 let currX = 0.0, currY = 0.0;
-let azimuth = 0.0, incline = 0.0;
 let stream = undefined;
 
 const $canvas = $("#main-canvas");
@@ -16,6 +15,26 @@ const { width, height } = canvas;
 const context = canvas.getContext("2d");
 const imgData = context.createImageData(width, height);
 
+// Build the scene
+const scene = Photon.createScene();
+scene.addObject({
+  transform: {
+    position: [0.0, 0.15, 0.0],
+    scale: [1.0, 1.0, 1.0],
+    rotation: [0.0, Math.sin(1.57), 0.0, Math.cos(1.57)],
+  },
+  intersectable: { type: "sphere", radius: 0.3 },
+});
+scene.addObject({
+  intersectable: "plane",
+});
+scene.addLight({
+  type: "sphere",
+  center: [1.0, 2.0, 1.0],
+  radius: 0.2,
+  radiance: [100.0, 100.0, 100.0],
+});
+
 $("#stop-button").click(() => {
   if (stream) {
     stream.close();
@@ -39,16 +58,14 @@ $canvas.mousemove((event) => {
 
     // Calculate new azimuth and incline
     Photon.mainCamera.azimuth += diffX * 0.01;
-    Photon.mainCamera.incline = Math.max(Math.min(incline + diffY * 0.01, MAX_INCLINE), MIN_INCLINE);
+    Photon.mainCamera.incline = Math.max(Math.min(Photon.mainCamera.incline + diffY * 0.01, MAX_INCLINE), MIN_INCLINE);
 
     currX = nextX;
     currY = nextY;
 
-    // if (stream) {
-    //   stream.close();
-    //   stream = undefined;
-    // }
-    // startRenderStream();
+    if (stream) {
+      stream.updateCamera(Photon.mainCamera);
+    }
   }
 });
 
@@ -57,7 +74,7 @@ $canvas.mouseup(() => {
 });
 
 function startRenderStream() {
-  stream = Photon.createRenderStream(imgData, (event) => {
+  stream = Photon.createRenderStream(imgData, scene, { integrator: "whitted" }, (event) => {
     if (event.type === "update") {
       context.putImageData(imgData, 0, 0);
     }
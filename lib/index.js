@@ -1,16 +1,43 @@
 var addon = require('../native');
 
+// Wraps each `ArrayBuffer` of the AOVs returned by the addon into a
+// `Float32Array`
+function toFloat32Arrays(aovs) {
+  const result = {};
+  for (const name of Object.keys(aovs)) {
+    result[name] = new Float32Array(aovs[name]);
+  }
+  return result;
+}
+
+// The addon writes pixels into a `Buffer` only, so a typed array such as the
+// `Uint8ClampedArray` of a canvas `ImageData` gets wrapped into a `Buffer`
+// sharing its memory
+function nativeImageData(imgData) {
+  const data = imgData.data;
+  if (!ArrayBuffer.isView(data) || Buffer.isBuffer(data)) {
+    return imgData;
+  }
+  return {
+    width: imgData.width,
+    height: imgData.height,
+    data: Buffer.from(data.buffer, data.byteOffset, data.byteLength),
+  };
+}
+
 class RenderStream {
-  constructor(imgData, camera, callback) {
+  constructor(imgData, scene, camera, options, callback) {
     this.imgData = imgData;
+    this.scene = scene;
     this.camera = camera;
-    this.stream = new addon.RenderStream(imgData, camera);
+    this.stream = new addon.RenderStream(imgData, scene, camera, options);
+    this.closed = false;
     this.finished = false;
 
     let self = this;
 
     function pollEvent() {
-      if (self.finished) {
+      if (self.closed) {
         self.stream.shutdown();
         return;
       }
@@ -27,26 +54,55 @@ class RenderStream {
                 self.imgData.data[index + 3] = event.a;
               }
             }
-            setImmediate(pollEvent);
+          } else if (event.type === 'set_rows') {
+            self.imgData.data.set(event.pixels, event.y * imgData.width * 4);
           } else if (event.type === 'update') {
             callback(event);
-            setImmediate(pollEvent);
           } else if (event.type === 'finish') {
             callback(event);
             self.finished = true;
           }
-        } else {
-          console.log("undefined event");
-          setImmediate(pollEvent);
         }
+
+        // Keep polling even after finishing since the camera or the scene
+        // may still be updated
+        setImmediate(pollEvent);
       });
     }
 
     setImmediate(pollEvent);
   }
 
+  updateCamera(camera) {
+    this.camera = camera;
+    this.finished = false;
+    this.stream.updateCamera(camera);
+  }
+
+  updateScene(scene) {
+    this.scene = scene;
+    this.finished = false;
+    this.stream.updateScene(scene);
+  }
+
   close() {
-    this.finished = true;
+    this.closed = true;
+  }
+
+  // AOVs of the finished render as an object of `Float32Array`s keyed by
+  // AOV name
+  getAovs() {
+    if (!this.finished) {
+      throw new Error('The render has not finished yet');
+    }
+    return toFloat32Arrays(this.stream.aovs());
+  }
+
+  saveImage(path, format) {
+    if (!this.finished) {
+      throw new Error('The render has not finished yet');
+    }
+    this.stream.saveImage(path, format);
   }
 
   isFinished() {
@@ -66,21 +122,58 @@ module.exports = {
     distance: 3.0,
   },
 
-  render(imgData) {
+  createScene() {
+    return new addon.Scene();
+  },
+
+  loadObj(path) {
+    return addon.loadObj(path);
+  },
+
+  // Loads a JSON scene file into `{ scene, camera, render }`. The camera can
+  // replace `mainCamera` and the render settings serve as render options.
+  loadScene(path) {
+    return addon.loadScene(path);
+  },
+
+  // Returns the AOVs listed in `options.aovs`, e.g. `["depth", "normal"]`,
+  // as an object of `Float32Array`s keyed by AOV name
+  render(imgData, scene, options = {}) {
     const start = new Date();
-    addon.render(imgData, this.mainCamera);
+    const aovs = addon.render(nativeImageData(imgData), scene, this.mainCamera, options);
     const end = new Date();
     console.log(`[render] time elapsed: ${end - start}`);
+    return toFloat32Arrays(aovs);
+  },
+
+  renderAsync(imgData, scene, options = {}, callback = () => {}) {
+    const start = new Date();
+    addon.renderAsync(imgData, scene, this.mainCamera, options, (err, result) => {
+      if (err) {
+        callback(err, imgData, {});
+        return;
+      }
+      imgData.data.set(result.pixels);
+      const end = new Date();
+      console.log(`[renderAsync] time elapsed: ${end - start}`);
+      callback(err, imgData, toFloat32Arrays(result.aovs));
+    });
+  },
+
+  // Object under pixel `(x, y)` of a `width` by `height` view through the
+  // camera, as `{ id, position, normal, t, uv }`, or null
+  pick(scene, camera, x, y, width, height) {
+    return addon.pick(scene, camera, x, y, width, height);
   },
 
   fillBlack(imgData) {
     const start = new Date();
-    addon.fillBlack(imgData);
+    addon.fillBlack(nativeImageData(imgData));
     const end = new Date();
     console.log(`[fillBlackRust] time elapsed: ${end - start}`);
   },
 
-  createRenderStream(imgData, callback) {
-    return new RenderStream(imgData, this.mainCamera, callback);
+  createRenderStream(imgData, scene, options, callback) {
+    return new RenderStream(imgData, scene, this.mainCamera, options, callback);
   },
 }
\ No newline at end of file
@@ -1,6 +1,8 @@
+#[cfg(feature = "addon")]
 extern crate neon_build;
 
 fn main() {
+    #[cfg(feature = "addon")]
     neon_build::setup(); // must be called in build.rs
 
     // add project-specific build logic here...
@@ -0,0 +1,273 @@
+//! Functions and classes exported to JS by the Node addon
+
+use neon::prelude::*;
+
+use std::path::Path;
+use std::sync::Mutex;
+
+use util::{ImageData, ImageDimension};
+use scene::Scene;
+use camera::Camera;
+use renderer::RenderOptions;
+use scene_builder::{JsScene, JsMesh, JsMaterial};
+use obj::{ObjPart, ObjModel};
+use aov::{AovBuffer, render_aovs};
+use convert::{camera_from_js, options_from_js, json_to_js, vector3_to_js, aovs_to_js};
+use validate::{image_dimension_from_js, image_buffer_from_js, count};
+
+/// Renders into `imgData` and returns the AOVs asked for in the options as
+/// an object of `ArrayBuffer`s holding floats, keyed by AOV name
+fn render(mut cx: FunctionContext) -> JsResult<JsObject> {
+
+  let img_data: Handle<JsObject> = cx.argument::<JsObject>(0)?;
+  let ImageDimension { width, height } = image_dimension_from_js(&mut cx, img_data)?;
+  let mut buffer = image_buffer_from_js(&mut cx, img_data, &ImageDimension { width, height })?;
+
+  let mut scene = cx.argument::<JsScene>(1)?;
+
+  let camera: Handle<JsObject> = cx.argument::<JsObject>(2)?;
+  let camera = camera_from_js(&mut cx, camera)?;
+
+  let options = cx.argument_opt(3);
+  let options = options_from_js(&mut cx, options)?;
+
+  let aovs = { // Tricks to get rid of borrow checker
+
+    // Setup image data
+    let guard = cx.lock();
+    let data = buffer.borrow_mut(&guard);
+    let slice = data.as_mut_slice::<u8>();
+    let mut img_data = ImageData { width, height, buffer: slice };
+
+    // Borrow the scene, bringing its BVH up to date
+    let mut scene = scene.borrow_mut(&guard);
+    scene.build_bvh();
+
+    // Render to image data
+    ::renderer::render(&scene, &camera, &mut img_data, &options);
+    render_aovs(&scene, &camera, &ImageDimension { width, height }, &options.aovs, options.threads)
+  };
+
+  aovs_to_js(&mut cx, &aovs)
+}
+
+/// Renders into a buffer of its own off the main thread. The BVH is built
+/// there too, so the scene is locked for the duration of `perform`.
+struct RenderTask {
+  scene: Mutex<Scene>,
+  camera: Camera,
+  options: RenderOptions,
+  width: usize,
+  height: usize,
+}
+
+impl Task for RenderTask {
+  type Output = (Vec<u8>, Vec<AovBuffer>);
+  type Error = String;
+  type JsEvent = JsObject;
+
+  fn perform(&self) -> Result<Self::Output, Self::Error> {
+    let mut scene = self.scene.lock().map_err(|_| "Could not obtain lock on scene".to_string())?;
+    scene.build_bvh();
+    let mut pixels = vec![0; self.width * self.height * 4];
+    {
+      let mut img_data = ImageData { width: self.width, height: self.height, buffer: &mut pixels };
+      ::renderer::render(&scene, &self.camera, &mut img_data, &self.options);
+    }
+    let img_dim = ImageDimension { width: self.width, height: self.height };
+    let aovs = render_aovs(&scene, &self.camera, &img_dim, &self.options.aovs, self.options.threads);
+    Ok((pixels, aovs))
+  }
+
+  fn complete(self, mut cx: TaskContext, output: Result<Self::Output, Self::Error>) -> JsResult<Self::JsEvent> {
+    let (pixels, aovs) = output.or_else(|err| cx.throw_error(&err))?;
+    let mut buffer = cx.buffer(pixels.len() as u32)?;
+    cx.borrow_mut(&mut buffer, |data| data.as_mut_slice::<u8>().copy_from_slice(&pixels));
+    let aovs = aovs_to_js(&mut cx, &aovs)?;
+    let result = cx.empty_object();
+    result.set(&mut cx, "pixels", buffer)?;
+    result.set(&mut cx, "aovs", aovs)?;
+    Ok(result)
+  }
+}
+
+/// Same arguments as `render` followed by a `function (err, result)`
+/// callback, which receives `{ pixels, aovs }` with the RGBA pixels in a new
+/// buffer
+fn render_async(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+
+  let img_data: Handle<JsObject> = cx.argument::<JsObject>(0)?;
+  let ImageDimension { width, height } = image_dimension_from_js(&mut cx, img_data)?;
+
+  // Render a snapshot so that the scene may keep changing meanwhile
+  let scene = cx.argument::<JsScene>(1)?;
+  let scene = cx.borrow(&scene, |scene| scene.clone());
+
+  let camera: Handle<JsObject> = cx.argument::<JsObject>(2)?;
+  let camera = camera_from_js(&mut cx, camera)?;
+
+  let options = cx.argument_opt(3);
+  let options = options_from_js(&mut cx, options)?;
+
+  let callback = cx.argument::<JsFunction>(4)?;
+  RenderTask { scene: Mutex::new(scene), camera, options, width, height }.schedule(callback);
+
+  Ok(cx.undefined())
+}
+
+fn fill_black(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+
+  let img_data: Handle<JsObject> = cx.argument::<JsObject>(0)?;
+  let ImageDimension { width, height } = image_dimension_from_js(&mut cx, img_data)?;
+  let mut buffer = image_buffer_from_js(&mut cx, img_data, &ImageDimension { width, height })?;
+
+  { // Tricks to get rid of borrow checker
+    let guard = cx.lock();
+    let data = buffer.borrow_mut(&guard);
+    let slice = data.as_mut_slice::<u8>();
+
+    for x in 0..width {
+      for y in 0..height {
+        let index: usize = (y * width + x) * 4;
+        slice[index] = 0;
+        slice[index + 1] = 0;
+        slice[index + 2] = 0;
+        slice[index + 3] = 255;
+      }
+    }
+  }
+
+  Ok(cx.undefined())
+}
+
+/// Shoots the ray through the center of pixel `(x, y)` of a `width` by
+/// `height` image. Returns `{ id, position, normal, t, uv }` for the closest
+/// object hit, `uv` being null for shapes without texture coordinates, or
+/// null when the ray escapes.
+fn pick(mut cx: FunctionContext) -> JsResult<JsValue> {
+  let mut scene = cx.argument::<JsScene>(0)?;
+  let camera: Handle<JsObject> = cx.argument::<JsObject>(1)?;
+  let camera = camera_from_js(&mut cx, camera)?;
+  let mut pixel = [0; 4];
+  for (i, name) in ["x", "y", "width", "height"].iter().enumerate() {
+    let value = cx.argument::<JsValue>(i as i32 + 2)?;
+    pixel[i] = count(&mut cx, value, name)?;
+  }
+  let [x, y, width, height] = pixel;
+  if x >= width || y >= height {
+    return cx.throw_range_error(format!("Pixel ({}, {}) lies outside of the {}x{} image", x, y, width, height));
+  }
+
+  let ray = camera.ray(x, y, width, height);
+  let hit = {
+    let guard = cx.lock();
+    let mut scene = scene.borrow_mut(&guard);
+    scene.build_bvh();
+    // `Scene::intersect` names the object of every hit, so the id is never
+    // made up
+    scene.intersect(&ray).and_then(|itsct| itsct.object.map(|id| (id, itsct.position, itsct.normal, itsct.t, itsct.uv)))
+  };
+  let (object, position, normal, t, uv) = match hit {
+    Some(hit) => hit,
+    None => return Ok(cx.null().upcast())
+  };
+
+  let result = cx.empty_object();
+  let id = cx.number(object as f64);
+  result.set(&mut cx, "id", id)?;
+  let position = vector3_to_js(&mut cx, position)?;
+  result.set(&mut cx, "position", position)?;
+  let normal = vector3_to_js(&mut cx, normal)?;
+  result.set(&mut cx, "normal", normal)?;
+  let t = cx.number(t);
+  result.set(&mut cx, "t", t)?;
+  let uv: Handle<JsValue> = match uv {
+    Some((u, v)) => {
+      let arr = JsArray::new(&mut cx, 2);
+      let (u, v) = (cx.number(u), cx.number(v));
+      arr.set(&mut cx, 0, u)?;
+      arr.set(&mut cx, 1, v)?;
+      arr.upcast()
+    },
+    None => cx.null().upcast()
+  };
+  result.set(&mut cx, "uv", uv)?;
+  Ok(result.upcast())
+}
+
+/// Loads an OBJ file into an array of `{ name, intersectable, material }`
+/// object descriptors, one per group and material, ready for `addObject`.
+/// Problems worked around while loading go to `console.warn`.
+fn load_obj(mut cx: FunctionContext) -> JsResult<JsArray> {
+  let path = cx.argument::<JsString>(0)?.value();
+  let ObjModel { parts, warnings } = match ::obj::load_obj(Path::new(&path)) {
+    Ok(model) => model,
+    Err(err) => return cx.throw_error(err.to_string())
+  };
+  if !warnings.is_empty() {
+    let console = cx.global().get(&mut cx, "console")?.downcast_or_throw::<JsObject, _>(&mut cx)?;
+    let warn = console.get(&mut cx, "warn")?.downcast_or_throw::<JsFunction, _>(&mut cx)?;
+    for warning in warnings {
+      let message = cx.string(warning);
+      warn.call(&mut cx, console, vec![message])?;
+    }
+  }
+
+  let descriptors = JsArray::new(&mut cx, parts.len() as u32);
+  for (i, part) in parts.into_iter().enumerate() {
+    let ObjPart { name, mesh, material } = part;
+    let mut js_mesh = JsMesh::new(&mut cx, Vec::<Handle<JsValue>>::new())?;
+    cx.borrow_mut(&mut js_mesh, |mut js_mesh| **js_mesh = mesh);
+    let mut js_material = JsMaterial::new(&mut cx, Vec::<Handle<JsValue>>::new())?;
+    cx.borrow_mut(&mut js_material, |mut js_material| **js_material = material);
+
+    let descriptor = cx.empty_object();
+    let name = cx.string(name);
+    descriptor.set(&mut cx, "name", name)?;
+    descriptor.set(&mut cx, "intersectable", js_mesh)?;
+    descriptor.set(&mut cx, "material", js_material)?;
+    descriptors.set(&mut cx, i as u32, descriptor)?;
+  }
+  Ok(descriptors)
+}
+
+/// Loads a JSON scene file (see `docs/scene-format.md`) into `{ scene,
+/// camera, render }`, where the camera and the render settings are plain
+/// objects usable as the camera and the options of the render functions
+fn load_scene(mut cx: FunctionContext) -> JsResult<JsObject> {
+  let path = cx.argument::<JsString>(0)?.value();
+  let file = match ::scene_file::load_scene_file(Path::new(&path)) {
+    Ok(file) => file,
+    Err(err) => return cx.throw_error(err.to_string())
+  };
+  let (camera, render) = match (serde_json::to_value(&file.camera), serde_json::to_value(&file.render)) {
+    (Ok(camera), Ok(render)) => (camera, render),
+    (Err(err), _) | (_, Err(err)) => return cx.throw_error(err.to_string())
+  };
+
+  let mut js_scene = JsScene::new(&mut cx, Vec::<Handle<JsValue>>::new())?;
+  let scene = file.scene;
+  cx.borrow_mut(&mut js_scene, |mut js_scene| **js_scene = scene);
+  let camera = json_to_js(&mut cx, &camera)?;
+  let render = json_to_js(&mut cx, &render)?;
+
+  let result = cx.empty_object();
+  result.set(&mut cx, "scene", js_scene)?;
+  result.set(&mut cx, "camera", camera)?;
+  result.set(&mut cx, "render", render)?;
+  Ok(result)
+}
+
+register_module!(mut cx, {
+  cx.export_function("render", render)?;
+  cx.export_function("renderAsync", render_async)?;
+  cx.export_function("fillBlack", fill_black)?;
+  cx.export_class::<::render_stream::Stream>("RenderStream")?;
+  cx.export_class::<JsScene>("Scene")?;
+  cx.export_class::<JsMesh>("Mesh")?;
+  cx.export_class::<JsMaterial>("Material")?;
+  cx.export_function("loadObj", load_obj)?;
+  cx.export_function("loadScene", load_scene)?;
+  cx.export_function("pick", pick)?;
+  Ok(())
+});
@@ -0,0 +1,151 @@
+use serde_json;
+
+use ::math::Vector3;
+use ::camera::Camera;
+use ::material::Material;
+use ::scene::Scene;
+use ::util::{ImageDimension, parallel_map};
+
+/// Arbitrary output variable: a quantity of the first surface seen through
+/// each pixel, rendered alongside the color for compositing
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Aov {
+  /// Distance from the camera along the ray, infinite for the background
+  Depth,
+
+  /// World space outward facing geometric normal
+  Normal,
+
+  /// World space hit position
+  Position,
+
+  /// Overall reflectance of the material
+  Albedo,
+
+  /// Id of the hit object, -1 for the background
+  ObjectId,
+
+  /// Id shared by every material with the same parameters, -1 for the
+  /// background
+  MaterialId,
+}
+
+impl Aov {
+  /// Parse `"depth"`, `"normal"`, `"position"`, `"albedo"`, `"objectId"` or
+  /// `"materialId"`
+  pub fn from_name(name: &str) -> Option<Self> {
+    match name {
+      "depth" => Some(Aov::Depth),
+      "normal" => Some(Aov::Normal),
+      "position" => Some(Aov::Position),
+      "albedo" => Some(Aov::Albedo),
+      "objectId" => Some(Aov::ObjectId),
+      "materialId" => Some(Aov::MaterialId),
+      _ => None,
+    }
+  }
+
+  pub fn name(&self) -> &'static str {
+    match self {
+      Aov::Depth => "depth",
+      Aov::Normal => "normal",
+      Aov::Position => "position",
+      Aov::Albedo => "albedo",
+      Aov::ObjectId => "objectId",
+      Aov::MaterialId => "materialId",
+    }
+  }
+
+  /// Channel names, which become `<name>.<channel>` in EXR files
+  pub fn channels(&self) -> &'static [&'static str] {
+    match self {
+      Aov::Depth => &["Z"],
+      Aov::Normal | Aov::Position => &["X", "Y", "Z"],
+      Aov::Albedo => &["R", "G", "B"],
+      Aov::ObjectId | Aov::MaterialId => &["id"],
+    }
+  }
+
+  fn background(&self) -> f32 {
+    match self {
+      Aov::Depth => f32::INFINITY,
+      Aov::ObjectId | Aov::MaterialId => -1.0,
+      _ => 0.0,
+    }
+  }
+}
+
+/// One AOV over the whole image, with the channels of each pixel stored
+/// next to each other in top to bottom rows
+#[derive(Clone)]
+pub struct AovBuffer {
+  pub aov: Aov,
+  pub width: usize,
+  pub height: usize,
+  pub data: Vec<f32>,
+}
+
+impl AovBuffer {
+  /// Values of a single channel
+  pub fn channel(&self, index: usize) -> Vec<f32> {
+    let stride = self.aov.channels().len();
+    self.data.iter().skip(index).step_by(stride).cloned().collect()
+  }
+}
+
+/// Id of the material parameters, small enough to be exactly representable
+/// as a float. Hashes the serialized parameters with 32 bit FNV-1a so that
+/// ids do not change between runs.
+pub fn material_id(material: &dyn Material) -> u32 {
+  let json = serde_json::to_string(&material.describe()).unwrap_or_default();
+  let hash = json.bytes().fold(0x811c_9dc5u32, |hash, byte| (hash ^ byte as u32).wrapping_mul(0x0100_0193));
+  hash & 0x00ff_ffff
+}
+
+/// Render the given AOVs with one ray through the center of every pixel.
+/// AOVs are not filtered, as averaging ids or depths across edges would
+/// give values belonging to neither side.
+pub fn render_aovs(scene: &Scene, camera: &Camera, img_dim: &ImageDimension, aovs: &[Aov], threads: usize) -> Vec<AovBuffer> {
+  if aovs.is_empty() {
+    return vec![];
+  }
+  let rows: Vec<usize> = (0..img_dim.height).collect();
+  let row_values = parallel_map(threads.max(1), &rows, |&y| {
+    let mut values: Vec<Vec<f32>> = aovs.iter().map(|aov| Vec::with_capacity(img_dim.width * aov.channels().len())).collect();
+    for x in 0..img_dim.width {
+      let ray = camera.ray(x, y, img_dim.width, img_dim.height);
+      let itsct = scene.intersect(&ray);
+      for (aov, values) in aovs.iter().zip(values.iter_mut()) {
+        match itsct {
+          Some(ref itsct) => match aov {
+            Aov::Depth => values.push(itsct.t),
+            Aov::Normal => values.extend_from_slice(&[itsct.normal.x, itsct.normal.y, itsct.normal.z]),
+            Aov::Position => values.extend_from_slice(&[itsct.position.x, itsct.position.y, itsct.position.z]),
+            Aov::Albedo => {
+              let albedo = itsct.material.map_or(Vector3::zero(), |material| material.albedo());
+              values.extend_from_slice(&[albedo.x, albedo.y, albedo.z]);
+            },
+            Aov::ObjectId => values.push(itsct.object.map_or(-1.0, |id| id as f32)),
+            Aov::MaterialId => values.push(itsct.material.map_or(-1.0, |material| material_id(material) as f32)),
+          },
+          None => values.extend(aov.channels().iter().map(|_| aov.background())),
+        }
+      }
+    }
+    values
+  });
+
+  let mut buffers: Vec<AovBuffer> = aovs.iter().map(|&aov| AovBuffer {
+    aov,
+    width: img_dim.width,
+    height: img_dim.height,
+    data: Vec::with_capacity(img_dim.width * img_dim.height * aov.channels().len()),
+  }).collect();
+  for row in row_values {
+    for (buffer, values) in buffers.iter_mut().zip(row) {
+      buffer.data.extend(values);
+    }
+  }
+  buffers
+}
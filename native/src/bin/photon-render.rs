@@ -0,0 +1,243 @@
+//! Headless renderer for machines without Node or Electron, linking the
+//! library without the Neon addon when built with `--no-default-features`
+
+#[macro_use]
+extern crate photon_proto;
+
+use std::env;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::Instant;
+
+use photon_proto::{obj, scene_file};
+use photon_proto::math::Vector3;
+use photon_proto::util::{ImageDimension, Transform};
+use photon_proto::scene::Scene;
+use photon_proto::camera::CameraDescriptor;
+use photon_proto::object::Object;
+use photon_proto::bounded::{Bounded, BoundingBox};
+use photon_proto::light::DirectionalLight;
+use photon_proto::renderer::{RenderSettings, render_film};
+use photon_proto::sampler::SamplerKind;
+use photon_proto::filter::FilterDescriptor;
+use photon_proto::tonemap::ToneMapOperator;
+use photon_proto::output::{ImageFormat, save_film};
+use photon_proto::aov::{Aov, render_aovs};
+
+const USAGE: &str = "Usage: photon-render <scene.obj | scene.json> [options]
+
+Scene files bring their own camera and render settings, which the options
+below override. The defaults given are those of OBJ files.
+
+Options:
+  -o, --output <path>       Image to write [default: render.png]
+  -f, --format <format>     png, ppm, pfm, exr or exr32 [default: from the extension]
+  -w, --width <pixels>      Image width [default: 640]
+  -h, --height <pixels>     Image height [default: 480]
+  -i, --integrator <name>   normal, depth, ao, whitted or path [default: path]
+      --max-depth <n>       Bounces of the whitted and path integrators
+  -s, --spp <n>             Samples per pixel [default: 16]
+  -t, --threads <n>         Worker threads [default: logical cores]
+      --sampler <name>      random, stratified, halton or sobol [default: random]
+      --filter <name>       box, tent, gaussian or mitchell [default: box]
+      --exposure <stops>    Exposure adjustment [default: 0]
+      --tonemap <name>      linear, reinhard or aces [default: linear]
+      --azimuth <radians>   Camera azimuth around the scene [default: 0.5]
+      --incline <radians>   Camera incline [default: 0.4]
+      --distance <units>    Camera distance [default: fits the scene]
+      --aov <names>         Comma separated depth, normal, position, albedo, objectId
+                            or materialId layers to add to EXR output
+      --help                Print this message";
+
+struct Args {
+  scene: PathBuf,
+  output: PathBuf,
+  format: Option<ImageFormat>,
+
+  /// Render and camera options in the order given, applied on top of the
+  /// settings of the scene
+  overrides: Vec<(String, String)>,
+}
+
+fn fail(message: &str) -> ! {
+  eprintln!("photon-render: {}\n\n{}", message, USAGE);
+  process::exit(1);
+}
+
+fn parse<T: std::str::FromStr>(flag: &str, value: &str) -> T {
+  value.parse().unwrap_or_else(|_| fail(&format!("invalid value \"{}\" for {}", value, flag)))
+}
+
+/// Image sizes must be positive, an empty image cannot be saved
+fn parse_size(flag: &str, value: &str) -> usize {
+  match parse(flag, value) {
+    0 => fail(&format!("invalid value \"{}\" for {}", value, flag)),
+    size => size
+  }
+}
+
+fn parse_args() -> Args {
+  let mut args = Args {
+    scene: PathBuf::new(),
+    output: PathBuf::from("render.png"),
+    format: None,
+    overrides: vec![],
+  };
+
+  let mut scene = None;
+  let mut argv = env::args().skip(1);
+  while let Some(arg) = argv.next() {
+    if arg == "--help" {
+      println!("{}", USAGE);
+      process::exit(0);
+    }
+    if !arg.starts_with('-') {
+      if scene.is_some() {
+        fail(&format!("unexpected argument \"{}\"", arg));
+      }
+      scene = Some(PathBuf::from(arg));
+      continue;
+    }
+    let value = argv.next().unwrap_or_else(|| fail(&format!("missing value for {}", arg)));
+    match arg.as_str() {
+      "-o" | "--output" => args.output = PathBuf::from(value),
+      "-f" | "--format" => {
+        let format = ImageFormat::from_name(&value);
+        args.format = Some(format.unwrap_or_else(|| fail(&format!("unknown format \"{}\"", value))));
+      },
+      "-w" | "--width" | "-h" | "--height" | "-i" | "--integrator" | "--max-depth" | "-s" | "--spp" |
+      "-t" | "--threads" | "--sampler" | "--filter" | "--exposure" | "--tonemap" | "--azimuth" |
+      "--incline" | "--distance" | "--aov" => args.overrides.push((arg, value)),
+      _ => fail(&format!("unknown option \"{}\"", arg)),
+    }
+  }
+  args.scene = scene.unwrap_or_else(|| fail("missing scene file"));
+  args
+}
+
+fn apply_overrides(args: &Args, settings: &mut RenderSettings, camera: &mut CameraDescriptor) {
+  for (arg, value) in &args.overrides {
+    match arg.as_str() {
+      "-w" | "--width" => settings.width = parse_size(arg, value),
+      "-h" | "--height" => settings.height = parse_size(arg, value),
+      "-i" | "--integrator" => settings.integrator = value.clone(),
+      "--max-depth" => settings.max_depth = Some(parse(arg, value)),
+      "-s" | "--spp" => settings.spp = Some(parse::<usize>(arg, value).max(1)),
+      "-t" | "--threads" => settings.threads = Some(parse::<usize>(arg, value).max(1)),
+      "--sampler" => {
+        let sampler = SamplerKind::from_name(value);
+        settings.sampler = sampler.unwrap_or_else(|| fail(&format!("unknown sampler \"{}\"", value)));
+      },
+      "--filter" => {
+        settings.filter = match value.as_str() {
+          "box" => FilterDescriptor::Box { radius: None },
+          "tent" => FilterDescriptor::Tent { radius: None },
+          "gaussian" => FilterDescriptor::Gaussian { radius: None, alpha: None },
+          "mitchell" => FilterDescriptor::Mitchell { radius: None, b: None, c: None },
+          _ => fail(&format!("unknown filter \"{}\"", value)),
+        };
+      },
+      "--exposure" => settings.exposure = parse(arg, value),
+      "--tonemap" => {
+        let operator = ToneMapOperator::from_name(value);
+        settings.tone_map = operator.unwrap_or_else(|| fail(&format!("unknown tone mapping \"{}\"", value)));
+      },
+      "--azimuth" => camera.azimuth = parse(arg, value),
+      "--incline" => camera.incline = parse(arg, value),
+      "--distance" => camera.distance = parse(arg, value),
+      "--aov" => {
+        settings.aovs = value.split(',').filter(|name| !name.is_empty()).map(|name| {
+          Aov::from_name(name).unwrap_or_else(|| fail(&format!("unknown AOV \"{}\"", name)))
+        }).collect();
+      },
+      _ => unreachable!(),
+    }
+  }
+}
+
+/// Scene of an OBJ file, framed by a camera looking at the middle of it
+/// from far enough to see all of it
+fn load_obj_scene(path: &Path) -> Result<(Scene, CameraDescriptor, RenderSettings), String> {
+  let model = obj::load_obj(path).map_err(|err| err.to_string())?;
+  for warning in &model.warnings {
+    eprintln!("photon-render: warning: {}", warning);
+  }
+  let mut scene = Scene::new();
+  for part in model.parts {
+    scene.add_object(Object {
+      id: 0,
+      name: Some(part.name),
+      parent: None,
+      transform: Transform::identity(),
+      intersectable: Box::new(part.mesh),
+      material: part.material,
+    });
+  }
+
+  // OBJ files carry no lights, so light them with a sun over the shoulder
+  scene.add_light(Box::new(DirectionalLight::new(vec3!(-0.4, -1.0, -0.6), vec3!(3.0))));
+
+  let bounds = scene.objects.iter()
+    .map(|object| object.bounding_box())
+    .filter(|bb| bb.is_finite())
+    .fold(BoundingBox::empty(), |acc, bb| acc.union(&bb));
+  let (target, radius) = if bounds.is_finite() {
+    let diagonal = bounds.max - bounds.min;
+    (bounds.centroid(), diagonal.dot(&diagonal).sqrt() / 2.0)
+  } else {
+    (Vector3::zero(), 1.0)
+  };
+  let camera = CameraDescriptor { target, azimuth: 0.5, incline: 0.4, distance: radius * 1.8, ..CameraDescriptor::default() };
+  let settings = RenderSettings { integrator: String::from("path"), spp: Some(16), ..RenderSettings::default() };
+  Ok((scene, camera, settings))
+}
+
+fn load_scene(path: &Path) -> Result<(Scene, CameraDescriptor, RenderSettings), String> {
+  if path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("json")) {
+    let file = scene_file::load_scene_file(path).map_err(|err| err.to_string())?;
+    Ok((file.scene, file.camera, file.render))
+  } else {
+    load_obj_scene(path)
+  }
+}
+
+fn main() {
+  let args = parse_args();
+
+  let start = Instant::now();
+  let (mut scene, mut camera, mut settings) = load_scene(&args.scene).unwrap_or_else(|err| {
+    eprintln!("photon-render: {}", err);
+    process::exit(1);
+  });
+  scene.build_bvh();
+  println!("Loaded {} in {:.2?}", args.scene.display(), start.elapsed());
+
+  apply_overrides(&args, &mut settings, &mut camera);
+  if settings.width == 0 || settings.height == 0 {
+    eprintln!("photon-render: {}: expected a non-zero image size, got {}x{}", args.scene.display(), settings.width, settings.height);
+    process::exit(1);
+  }
+  let options = settings.to_options().unwrap_or_else(|err| fail(&err.to_string()));
+  let camera = camera.to_camera();
+  let format = args.format.or_else(|| ImageFormat::from_path(&args.output)).unwrap_or(ImageFormat::Png);
+  if !options.aovs.is_empty() && format != ImageFormat::ExrHalf && format != ImageFormat::ExrFloat {
+    fail("AOVs can only be saved to EXR files");
+  }
+
+  let start = Instant::now();
+  let img_dim = ImageDimension { width: settings.width, height: settings.height };
+  let film = render_film(&scene, &camera, &img_dim, &options, |rows| {
+    print!("\rRendering {:3}%", rows * 100 / img_dim.height.max(1));
+    io::stdout().flush().ok();
+  });
+  println!("\rRendered {}x{} at {} spp on {} threads in {:.2?}",
+    img_dim.width, img_dim.height, options.spp.unwrap_or(1), options.threads, start.elapsed());
+  let aovs = render_aovs(&scene, &camera, &img_dim, &options.aovs, options.threads);
+
+  if let Err(err) = save_film(&film, &aovs, &options.tone_mapper, &args.output, format) {
+    eprintln!("photon-render: {}: {}", args.output.display(), err);
+    process::exit(1);
+  }
+  println!("Saved {}", args.output.display());
+}
@@ -1,6 +1,8 @@
-use ::intersectable::{Cube, Sphere};
+use ::intersectable::{Cube, Sphere, Plane};
 use ::math::{Vector3, Matrix4};
+use ::util::Ray;
 
+#[derive(Debug, Clone, Copy)]
 pub struct BoundingBox {
   pub min: Vector3,
   pub max: Vector3,
@@ -11,9 +13,71 @@ impl BoundingBox {
     BoundingBox { min, max }
   }
 
+  /// Box containing nothing, the identity of `union`
+  pub fn empty() -> Self {
+    Self::new(vec3!(f32::INFINITY), vec3!(f32::NEG_INFINITY))
+  }
+
+  /// Box containing everything, for unbounded shapes such as planes
+  pub fn infinite() -> Self {
+    Self::new(vec3!(f32::NEG_INFINITY), vec3!(f32::INFINITY))
+  }
+
+  pub fn is_finite(&self) -> bool {
+    (0..3).all(|i| self.min[i].is_finite() && self.max[i].is_finite())
+  }
+
+  pub fn union(&self, other: &Self) -> Self {
+    Self::new(self.min.min(&other.min), self.max.max(&other.max))
+  }
+
+  pub fn extend(&self, point: Vector3) -> Self {
+    Self::new(self.min.min(&point), self.max.max(&point))
+  }
+
+  pub fn centroid(&self) -> Vector3 {
+    (self.min + self.max) * 0.5
+  }
+
+  pub fn surface_area(&self) -> f32 {
+    let d = self.max - self.min;
+    if d.x < 0.0 || d.y < 0.0 || d.z < 0.0 {
+      0.0
+    } else {
+      2.0 * (d.x * d.y + d.y * d.z + d.z * d.x)
+    }
+  }
+
+  /// Index of the axis along which the box is the longest
+  pub fn max_extent(&self) -> u8 {
+    let d = self.max - self.min;
+    if d.x > d.y && d.x > d.z { 0 } else if d.y > d.z { 1 } else { 2 }
+  }
+
+  /// Slab test. `inv_dir` is the component-wise reciprocal of the ray
+  /// direction, computed once per ray. Returns the entry distance if the
+  /// ray overlaps the box somewhere in `[0, t_max]`.
+  pub fn intersect(&self, ray: &Ray, inv_dir: Vector3, t_max: f32) -> Option<f32> {
+    let mut t0 = 0.0f32;
+    let mut t1 = t_max;
+    for i in 0..3 {
+      let near = (self.min[i] - ray.origin[i]) * inv_dir[i];
+      let far = (self.max[i] - ray.origin[i]) * inv_dir[i];
+      let (near, far) = if near > far { (far, near) } else { (near, far) };
+
+      // `max`/`min` drop the NaN produced by a ray lying in a slab plane
+      t0 = t0.max(near);
+      t1 = t1.min(far);
+      if t0 > t1 {
+        return None;
+      }
+    }
+    Some(t0)
+  }
+
   /// Arvo (1990) AABB Transform
   pub fn transform(&self, mat: Matrix4) -> Self {
-    let pos = Vector3::from(mat.row(3));
+    let pos = Vector3::from(mat.col(3));
     let mut bb = Self::new(pos, pos);
     for i in 0..3 {
       for j in 0..3 {
@@ -50,4 +114,10 @@ impl Bounded for Sphere {
     let v = vec3!(self.radius, self.radius, self.radius);
     BoundingBox::new(-v, v)
   }
-}
\ No newline at end of file
+}
+
+impl Bounded for Plane {
+  fn bounding_box(&self) -> BoundingBox {
+    BoundingBox::infinite()
+  }
+}
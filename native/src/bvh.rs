@@ -0,0 +1,236 @@
+use ::math::Vector3;
+use ::bounded::BoundingBox;
+use ::util::{Ray, Intersection};
+
+/// Number of buckets the centroid range is split into when evaluating the
+/// surface area heuristic
+const NUM_BUCKETS: usize = 12;
+
+/// Primitives per leaf below which splitting is never considered
+const MIN_LEAF_SIZE: usize = 2;
+
+/// Cost of visiting an interior node, relative to intersecting a primitive
+const TRAVERSAL_COST: f32 = 1.0;
+
+/// Node of the flattened tree. Nodes are laid out in depth first order so
+/// that the first child of an interior node directly follows it.
+#[derive(Clone)]
+struct BvhNode {
+  bounds: BoundingBox,
+
+  /// First primitive of a leaf, or index of the second child of an
+  /// interior node
+  offset: usize,
+
+  /// Number of primitives in a leaf, zero for interior nodes
+  count: usize,
+
+  /// Axis along which an interior node was split
+  axis: u8,
+}
+
+/// Bounding volume hierarchy over primitives identified by an arbitrary
+/// index, split with the surface area heuristic
+#[derive(Clone)]
+pub struct Bvh {
+  nodes: Vec<BvhNode>,
+  indices: Vec<usize>,
+}
+
+struct BuildPrimitive {
+  index: usize,
+  bounds: BoundingBox,
+  centroid: Vector3,
+}
+
+impl Bvh {
+  /// Build the tree from `(index, bounding box)` pairs. Boxes must be finite.
+  pub fn new(primitives: Vec<(usize, BoundingBox)>) -> Self {
+    let mut primitives: Vec<BuildPrimitive> = primitives.into_iter().map(|(index, bounds)| {
+      BuildPrimitive { index, bounds, centroid: bounds.centroid() }
+    }).collect();
+    let mut bvh = Self { nodes: Vec::with_capacity(2 * primitives.len()), indices: vec![] };
+    if !primitives.is_empty() {
+      bvh.build_node(&mut primitives, 0);
+    }
+    bvh.indices = primitives.iter().map(|p| p.index).collect();
+    bvh
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.nodes.is_empty()
+  }
+
+  /// Bounds of everything in the tree
+  pub fn bounding_box(&self) -> BoundingBox {
+    self.nodes.first().map_or(BoundingBox::empty(), |root| root.bounds)
+  }
+
+  /// Recursively build the subtree over `primitives`, which starts at
+  /// `offset` in the final index order. Returns the index of its root.
+  fn build_node(&mut self, primitives: &mut [BuildPrimitive], offset: usize) -> usize {
+    let bounds = primitives.iter().fold(BoundingBox::empty(), |acc, p| acc.union(&p.bounds));
+    let node_index = self.nodes.len();
+    self.nodes.push(BvhNode { bounds, offset, count: primitives.len(), axis: 0 });
+
+    let split = if primitives.len() <= MIN_LEAF_SIZE { None } else { Self::find_split(primitives, &bounds) };
+    if let Some((axis, mid)) = split {
+      let (left, right) = primitives.split_at_mut(mid);
+      self.build_node(left, offset);
+      let second = self.build_node(right, offset + mid);
+      let node = &mut self.nodes[node_index];
+      node.offset = second;
+      node.count = 0;
+      node.axis = axis;
+    }
+    node_index
+  }
+
+  /// Partition `primitives` along the axis of largest centroid extent at
+  /// the bucket boundary with the lowest SAH cost. Returns the axis and the
+  /// size of the first half, or `None` if a leaf is cheaper.
+  fn find_split(primitives: &mut [BuildPrimitive], bounds: &BoundingBox) -> Option<(u8, usize)> {
+    let centroid_bounds = primitives.iter().fold(BoundingBox::empty(), |acc, p| acc.extend(p.centroid));
+    let axis = centroid_bounds.max_extent();
+    let lo = centroid_bounds.min[axis];
+    let extent = centroid_bounds.max[axis] - lo;
+    if extent <= 0.0 {
+      // All centroids coincide, nothing to split
+      return None;
+    }
+
+    let bucket_of = |p: &BuildPrimitive| {
+      (((p.centroid[axis] - lo) / extent * NUM_BUCKETS as f32) as usize).min(NUM_BUCKETS - 1)
+    };
+    let mut counts = [0usize; NUM_BUCKETS];
+    let mut boxes = [BoundingBox::empty(); NUM_BUCKETS];
+    for p in primitives.iter() {
+      let b = bucket_of(p);
+      counts[b] += 1;
+      boxes[b] = boxes[b].union(&p.bounds);
+    }
+
+    // Cost of splitting after every bucket but the last
+    let total_area = bounds.surface_area();
+    let mut best = None;
+    for split in 0..NUM_BUCKETS - 1 {
+      let (left, right) = (0..NUM_BUCKETS).fold(
+        ((BoundingBox::empty(), 0), (BoundingBox::empty(), 0)),
+        |(left, right), b| if b <= split {
+          ((left.0.union(&boxes[b]), left.1 + counts[b]), right)
+        } else {
+          (left, (right.0.union(&boxes[b]), right.1 + counts[b]))
+        }
+      );
+      if left.1 == 0 || right.1 == 0 {
+        continue;
+      }
+      let cost = TRAVERSAL_COST
+        + (left.0.surface_area() * left.1 as f32 + right.0.surface_area() * right.1 as f32) / total_area;
+      if best.is_none_or(|(_, best_cost)| cost < best_cost) {
+        best = Some((split, cost));
+      }
+    }
+
+    let (split, cost) = best?;
+    if cost >= primitives.len() as f32 {
+      return None;
+    }
+    let mut mid = 0;
+    for i in 0..primitives.len() {
+      if bucket_of(&primitives[i]) <= split {
+        primitives.swap(i, mid);
+        mid += 1;
+      }
+    }
+    Some((axis, mid))
+  }
+
+  /// Closest hit among the primitives along `ray`. `intersect_primitive` is
+  /// called with the index of every primitive whose leaf the ray reaches.
+  pub fn intersect<'a, F>(&self, ray: &Ray, mut intersect_primitive: F) -> Option<Intersection<'a>>
+    where F: FnMut(usize) -> Option<Intersection<'a>>
+  {
+    if self.nodes.is_empty() {
+      return None;
+    }
+    let inv_dir = vec3!(1.0 / ray.direction.x, 1.0 / ray.direction.y, 1.0 / ray.direction.z);
+    let dir_is_neg = [inv_dir.x < 0.0, inv_dir.y < 0.0, inv_dir.z < 0.0];
+
+    let mut closest: Option<Intersection<'a>> = None;
+    let mut stack = Vec::with_capacity(64);
+    let mut current = 0;
+    loop {
+      let node = &self.nodes[current];
+      let t_max = closest.map_or(f32::INFINITY, |itsct| itsct.t);
+      if node.bounds.intersect(ray, inv_dir, t_max).is_some() {
+        if node.count > 0 {
+          for &index in &self.indices[node.offset..node.offset + node.count] {
+            closest = Intersection::min(closest, intersect_primitive(index));
+          }
+        } else if dir_is_neg[node.axis as usize] {
+          // Visit the child nearer to the ray origin first
+          stack.push(current + 1);
+          current = node.offset;
+          continue;
+        } else {
+          stack.push(node.offset);
+          current += 1;
+          continue;
+        }
+      }
+      match stack.pop() {
+        Some(next) => current = next,
+        None => break,
+      }
+    }
+    closest
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use ::math::Matrix4;
+  use ::bounded::Bounded;
+  use ::intersectable::{Intersectable, Sphere};
+  use ::sampler::{RandomSampler, Sampler};
+  use ::util::Placement;
+
+  fn random_vector(sampler: &mut RandomSampler, scale: f32) -> Vector3 {
+    let (x, y) = sampler.next_2d();
+    let z = sampler.next_1d();
+    vec3!(x - 0.5, y - 0.5, z - 0.5) * scale
+  }
+
+  #[test]
+  fn hits_match_brute_force() {
+    let mut sampler = RandomSampler::new(7);
+    let spheres: Vec<(Placement, Sphere)> = (0..300).map(|_| {
+      let center = random_vector(&mut sampler, 20.0);
+      let radius = 0.1 + sampler.next_1d();
+      (Placement::new(Matrix4::translate_matrix(center)).unwrap(), Sphere::new(radius))
+    }).collect();
+    let intersect_sphere = |index: usize, ray: &Ray| {
+      let (placement, sphere) = &spheres[index];
+      sphere.intersect(&ray.inverse_transform(placement)).map(|itsct| {
+        Intersection { object: Some(index), ..itsct.transform(placement) }
+      })
+    };
+    let bvh = Bvh::new(spheres.iter().enumerate().map(|(index, (placement, sphere))| {
+      (index, sphere.bounding_box().transform(placement.matrix))
+    }).collect());
+
+    let mut hits = 0;
+    for _ in 0..2000 {
+      let ray = Ray::new(random_vector(&mut sampler, 30.0), random_vector(&mut sampler, 1.0).normalize());
+      let expected = (0..spheres.len()).fold(None, |acc, index| Intersection::min(acc, intersect_sphere(index, &ray)));
+      let actual = bvh.intersect(&ray, |index| intersect_sphere(index, &ray));
+      assert_eq!(actual.and_then(|itsct| itsct.object), expected.and_then(|itsct| itsct.object));
+      hits += expected.is_some() as usize;
+    }
+
+    // Make sure the rays do not all miss
+    assert!(hits > 100, "only {} rays hit", hits);
+  }
+}
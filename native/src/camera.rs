@@ -1,7 +1,9 @@
-use ::math::{Vector3};
+use std::f32::consts::PI;
+
+use ::math::{Vector3, concentric_sample_disk, sample_regular_polygon};
 use ::util::{Ray};
 
-#[derive(Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct ThirdPersonCamera {
   pub target: Vector3,
   pub azimuth: f32,
@@ -9,14 +11,129 @@ pub struct ThirdPersonCamera {
   pub distance: f32,
 }
 
-#[derive(Clone, Debug)]
+/// How directions around the camera map onto the image. Film coordinates
+/// run from -1 to 1 along the image height, and proportionally wider along
+/// its width.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+pub enum Projection {
+
+  /// Pinhole projection with the camera's vertical field of view `fovy`
+  Perspective,
+
+  /// Parallel rays over a view `height` units tall
+  Orthographic {
+    #[serde(default = "default_ortho_height")]
+    height: f32,
+  },
+
+  /// Equidistant fisheye: the angle from `forward` grows linearly with the
+  /// distance from the image center, reaching `fov / 2` at the top and
+  /// bottom edges
+  Fisheye {
+    #[serde(default = "default_fisheye_fov")]
+    fov: f32,
+  },
+
+  /// Full 360 by 180 degree panorama, longitude along the width and
+  /// latitude along the height
+  Equirectangular,
+}
+
+fn default_ortho_height() -> f32 {
+  2.0
+}
+
+fn default_fisheye_fov() -> f32 {
+  PI
+}
+
+/// Orthonormal camera basis
+#[derive(Clone, Copy, Debug)]
+struct Frame {
+  forward: Vector3,
+  right: Vector3,
+  up: Vector3,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub struct Camera {
   pub position: Vector3,
   pub forward: Vector3,
   pub up: Vector3,
+  pub projection: Projection,
   pub fovy: f32,
+
+  /// Distance along `forward` of the plane in perfect focus
   pub focal_distance: f32,
+
+  /// Radius of the lens. Zero gives a pinhole camera without blur.
   pub aperture: f32,
+
+  /// Number of diaphragm blades shaping the lens into a polygon, less than
+  /// three for a round lens
+  pub aperture_blades: u32,
+
+  /// Angle of the first blade corner in radians
+  pub aperture_rotation: f32,
+}
+
+/// Camera as described in scene files, and by the JS bindings: orbiting
+/// `target` the way `ThirdPersonCamera` does, plus the lens settings
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase", deny_unknown_fields)]
+pub struct CameraDescriptor {
+  pub target: Vector3,
+  pub azimuth: f32,
+  pub incline: f32,
+  pub distance: f32,
+  pub projection: Projection,
+  pub fovy: f32,
+  pub aperture: f32,
+
+  /// Defaults to `distance`, keeping the target in focus
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub focal_distance: Option<f32>,
+  pub aperture_blades: u32,
+  pub aperture_rotation: f32,
+}
+
+impl Default for CameraDescriptor {
+  fn default() -> Self {
+    Self {
+      target: Vector3::zero(),
+      azimuth: 0.0,
+      incline: 0.3,
+      distance: 3.0,
+      projection: Projection::Perspective,
+      fovy: PI / 3.0,
+      aperture: 0.0,
+      focal_distance: None,
+      aperture_blades: 0,
+      aperture_rotation: 0.0,
+    }
+  }
+}
+
+impl CameraDescriptor {
+  pub fn to_camera(&self) -> Camera {
+    let camera = Camera::third_person(&ThirdPersonCamera {
+      target: self.target,
+      azimuth: self.azimuth,
+      incline: self.incline,
+      distance: self.distance,
+    });
+    Camera {
+      projection: self.projection,
+      fovy: self.fovy,
+      aperture: self.aperture.max(0.0),
+      focal_distance: self.focal_distance.unwrap_or(camera.focal_distance),
+      aperture_blades: self.aperture_blades,
+      aperture_rotation: self.aperture_rotation,
+      ..camera
+    }
+  }
 }
 
 impl Camera {
@@ -25,9 +142,12 @@ impl Camera {
       position,
       forward: forward.normalize(),
       up: Vector3::j(),
-      fovy: std::f32::consts::PI / 3.0,
+      projection: Projection::Perspective,
+      fovy: PI / 3.0,
       focal_distance: 1.0,
-      aperture: 0.01,
+      aperture: 0.0,
+      aperture_blades: 0,
+      aperture_rotation: 0.0,
     }
   }
 
@@ -42,46 +162,97 @@ impl Camera {
       tpc.azimuth.cos() * tpc.incline.cos()
     );
     let position = tpc.target + direction * tpc.distance;
-    Self::new(position, -direction)
+
+    // Keep the target in focus
+    Self { focal_distance: tpc.distance, ..Self::new(position, -direction) }
   }
 
-  pub fn rays<'a>(&'a self, width: usize, height: usize) -> CameraRays<'a> {
-    let w = self.forward; // front
-    let u = w.cross(self.up).normalize(); // right
-    let v = u.cross(w).normalize(); // up
-    let b = -(self.fovy / 2.0).tan();
-    let a = b * width as f32 / height as f32;
-    let hw = width as f32 / 2.0;
+  fn frame(&self) -> Frame {
+    let forward = self.forward;
+    let right = forward.cross(self.up).normalize();
+    let up = right.cross(forward).normalize();
+    Frame { forward, right, up }
+  }
+
+  /// Ray through the film position `(x, y)`, measured in pixels from the
+  /// top left corner of a `width` by `height` image
+  fn generate_ray(&self, frame: &Frame, x: f32, y: f32, width: usize, height: usize) -> Ray {
     let hh = height as f32 / 2.0;
+    let fx = (x - width as f32 / 2.0) / hh;
+    let fy = (hh - y) / hh;
+    match self.projection {
+      Projection::Perspective => {
+        let tan = (self.fovy / 2.0).tan();
+        let direction = frame.forward + frame.right * (fx * tan) + frame.up * (fy * tan);
+        Ray::new(self.position, direction.normalize())
+      },
+      Projection::Orthographic { height } => {
+        let half = height / 2.0;
+        let origin = self.position + frame.right * (fx * half) + frame.up * (fy * half);
+        Ray::new(origin, frame.forward)
+      },
+      Projection::Fisheye { fov } => {
+        let r = (fx * fx + fy * fy).sqrt();
+        let theta = r * fov / 2.0;
+        let (cos_phi, sin_phi) = if r > 0.0 { (fx / r, fy / r) } else { (1.0, 0.0) };
+        let direction = frame.forward * theta.cos()
+          + (frame.right * cos_phi + frame.up * sin_phi) * theta.sin();
+        Ray::new(self.position, direction.normalize())
+      },
+      Projection::Equirectangular => {
+        // Map the full width onto the longitude range whatever the aspect
+        let longitude = (x / width as f32 * 2.0 - 1.0) * PI;
+        let latitude = fy * PI / 2.0;
+        let direction = frame.forward * (latitude.cos() * longitude.cos())
+          + frame.right * (latitude.cos() * longitude.sin())
+          + frame.up * latitude.sin();
+        Ray::new(self.position, direction.normalize())
+      },
+    }
+  }
+
+  pub fn rays<'a>(&'a self, width: usize, height: usize) -> CameraRays<'a> {
     CameraRays {
       camera: self,
+      frame: self.frame(),
       i: 0,
       j: 0,
-      w,
-      u,
-      v,
-      b,
-      a,
-      hw,
-      hh,
       width,
       height,
     }
   }
 
+  /// Ray through the center of the pixel `(i, j)`
   pub fn ray(&self, i: usize, j: usize, width: usize, height: usize) -> Ray {
-    let w = self.forward; // front
-    let u = w.cross(self.up).normalize(); // right
-    let v = u.cross(w).normalize(); // up
-    let b = -(self.fovy / 2.0).tan();
-    let a = b * width as f32 / height as f32;
-    let hw = width as f32 / 2.0;
-    let hh = height as f32 / 2.0;
-    let origin = self.position;
-    let hor_dir = u * (a * (i as f32 - hw) / hw);
-    let ver_dir = v * (b * (j as f32 - hh) / hh);
-    let direction = (w + hor_dir + ver_dir).normalize();
-    Ray::new(origin, direction)
+    self.film_ray(i as f32 + 0.5, j as f32 + 0.5, width, height)
+  }
+
+  /// Ray through the continuous film position `(x, y)`, pixel `(i, j)`
+  /// covering `[i, i + 1) x [j, j + 1)`
+  pub fn film_ray(&self, x: f32, y: f32, width: usize, height: usize) -> Ray {
+    self.generate_ray(&self.frame(), x, y, width, height)
+  }
+
+  /// Turn a pinhole ray given by `ray` or `rays` into one leaving from a
+  /// point of the lens, sampled with `u`, while converging on the same
+  /// point of the focal plane
+  pub fn thin_lens(&self, ray: &Ray, u: (f32, f32)) -> Ray {
+    // Rays running parallel to or away from the focal plane stay sharp
+    let cos_theta = ray.direction.dot(&self.forward);
+    if self.aperture <= 0.0 || cos_theta <= 0.0 {
+      return Ray::new(ray.origin, ray.direction);
+    }
+    let Frame { right, up, .. } = self.frame();
+
+    // Point of the focal plane the pinhole ray would have hit
+    let focus = ray.point_at(self.focal_distance / cos_theta);
+    let (lx, ly) = if self.aperture_blades >= 3 {
+      sample_regular_polygon(u, self.aperture_blades, self.aperture_rotation)
+    } else {
+      concentric_sample_disk(u)
+    };
+    let origin = ray.origin + (right * lx + up * ly) * self.aperture;
+    Ray::new(origin, (focus - origin).normalize())
   }
 }
 
@@ -97,13 +268,7 @@ pub struct CameraRays<'a> {
   // Precomputation caches
   width: usize,
   height: usize,
-  hw: f32,
-  hh: f32,
-  w: Vector3,
-  u: Vector3,
-  v: Vector3,
-  a: f32,
-  b: f32,
+  frame: Frame,
 }
 
 impl<'a> Iterator for CameraRays<'a> {
@@ -132,13 +297,9 @@ impl<'a> Iterator for CameraRays<'a> {
     self.j = new_j;
 
     // Calculate ray
-    let origin = self.camera.position;
-    let hor_dir = self.u * (self.a * (old_i as f32 - self.hw) / self.hw);
-    let ver_dir = self.v * (self.b * (old_j as f32 - self.hh) / self.hh);
-    let direction = (self.w + hor_dir + ver_dir).normalize();
-    let ray = Ray::new(origin, direction);
+    let ray = self.camera.generate_ray(&self.frame, old_i as f32 + 0.5, old_j as f32 + 0.5, self.width, self.height);
 
     // Has the next ray
-    return Some((old_i, old_j, ray));
+    Some((old_i, old_j, ray))
   }
-}
\ No newline at end of file
+}
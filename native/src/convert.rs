@@ -0,0 +1,592 @@
+use std::sync::Arc;
+use std::f32::consts::PI;
+use neon::prelude::*;
+use serde_json::{self, Value, Map, Number};
+
+use math::{Vector3, Vector4, Quaternion};
+use util::Transform;
+use camera::{Camera, ThirdPersonCamera, Projection};
+use intersectable::{Intersectable, Sphere, Cube, Plane};
+use mesh::TriangleMesh;
+use material::{Material, Lambertian, Mirror, Glossy, Dielectric};
+use light::{Light, PointLight, DirectionalLight, SpotLight, SphereLight};
+use object::{Object as RenderObject, Group};
+use instance::{Instance, Prototype, PrototypePart};
+use renderer::{RenderOptions, RenderSettings, SettingsError};
+use scene_builder::{JsMesh, JsMaterial};
+use aov::AovBuffer;
+use validate::{field, is_absent, expected, check_fields, object, number, count, number_or, positive_or, count_or, string_or};
+
+/// Splits a descriptor given either by name or as `{ type, ... }` into its
+/// type and its parameters
+fn typed_descriptor<'a, C: Context<'a>>(cx: &mut C, value: Handle<'a, JsValue>, path: &str) -> NeonResult<(String, Handle<'a, JsObject>)> {
+  if let Ok(name) = value.downcast::<JsString>() {
+    return Ok((name.value(), JsObject::new(cx)));
+  }
+  let params = object(cx, value, path)?;
+  let ty = params.get(cx, "type")?;
+  match ty.downcast::<JsString>() {
+    Ok(ty) => Ok((ty.value(), params)),
+    Err(_) => expected(cx, &field(path, "type"), "a string", ty)
+  }
+}
+
+fn unknown_type<'a, C: Context<'a>, T>(cx: &mut C, path: &str, ty: &str, known: &str) -> NeonResult<T> {
+  cx.throw_type_error(format!("Unknown {} \"{}\", expected {}", field(path, "type"), ty, known))
+}
+
+/// Reads a flat array of numbers, e.g. packed vertex data. Absent values
+/// give an empty list.
+fn numbers_from_js<'a, C: Context<'a>>(cx: &mut C, value: Handle<'a, JsValue>, path: &str) -> NeonResult<Vec<f32>> {
+  if is_absent(value) {
+    return Ok(vec![]);
+  }
+  let arr = match value.downcast::<JsArray>() {
+    Ok(arr) => arr,
+    Err(_) => return expected(cx, path, "an array of numbers", value)
+  };
+  let mut numbers = Vec::with_capacity(arr.len() as usize);
+  for i in 0..arr.len() {
+    let element = arr.get(cx, i)?;
+    numbers.push(number(cx, element, &format!("{}[{}]", path, i))?);
+  }
+  Ok(numbers)
+}
+
+/// Reads `{ positions, normals, uvs, indices }` flat arrays into a mesh.
+/// Normals and UVs are optional, but must match the vertex count if given.
+pub fn mesh_from_js<'a, C: Context<'a>>(cx: &mut C, params: Handle<'a, JsObject>, path: &str) -> NeonResult<TriangleMesh> {
+  check_fields(cx, params, path, &["type", "positions", "normals", "uvs", "indices"])?;
+  let positions = params.get(cx, "positions")?;
+  let positions = numbers_from_js(cx, positions, &field(path, "positions"))?;
+  let normals = params.get(cx, "normals")?;
+  let normals = numbers_from_js(cx, normals, &field(path, "normals"))?;
+  let uvs = params.get(cx, "uvs")?;
+  let uvs = numbers_from_js(cx, uvs, &field(path, "uvs"))?;
+
+  let indices = params.get(cx, "indices")?;
+  let indices = match indices.downcast::<JsArray>() {
+    Ok(arr) => arr,
+    Err(_) => return expected(cx, &field(path, "indices"), "an array of indices", indices)
+  };
+  let mut flat_indices = Vec::with_capacity(indices.len() as usize);
+  for i in 0..indices.len() {
+    let index = indices.get(cx, i)?;
+    flat_indices.push(count(cx, index, &format!("{}.indices[{}]", path, i))? as u32);
+  }
+  match TriangleMesh::from_flat(&positions, &normals, &uvs, &flat_indices) {
+    Ok(mesh) => Ok(mesh),
+    Err(message) => cx.throw_range_error(format!("Invalid {}: {}", path, message)),
+  }
+}
+
+fn array_of_len<'a, C: Context<'a>>(cx: &mut C, value: Handle<'a, JsValue>, path: &str, len: u32) -> NeonResult<Vec<f32>> {
+  let arr = match value.downcast::<JsArray>() {
+    Ok(arr) => arr,
+    Err(_) => return expected(cx, path, &format!("an array of {} numbers", len), value)
+  };
+  if arr.len() != len {
+    return cx.throw_range_error(format!("Expected {} to have {} elements, got {}", path, len, arr.len()));
+  }
+  numbers_from_js(cx, value, path)
+}
+
+/// Reads a `[x, y, z]` array, falling back to `default` when the value is absent
+pub fn vector3_from_js<'a, C: Context<'a>>(cx: &mut C, value: Handle<'a, JsValue>, path: &str, default: Vector3) -> NeonResult<Vector3> {
+  if is_absent(value) {
+    return Ok(default);
+  }
+  let v = array_of_len(cx, value, path, 3)?;
+  Ok(vec3!(v[0], v[1], v[2]))
+}
+
+pub fn vector3_to_js<'a, C: Context<'a>>(cx: &mut C, v: Vector3) -> JsResult<'a, JsArray> {
+  let arr = JsArray::new(cx, 3);
+  for (i, &c) in [v.x, v.y, v.z].iter().enumerate() {
+    let c = cx.number(c);
+    arr.set(cx, i as u32, c)?;
+  }
+  Ok(arr)
+}
+
+/// Reads a `[x, y, z, w]` quaternion array, falling back to identity
+pub fn quaternion_from_js<'a, C: Context<'a>>(cx: &mut C, value: Handle<'a, JsValue>, path: &str) -> NeonResult<Quaternion> {
+  if is_absent(value) {
+    return Ok(Quaternion::identity());
+  }
+  let q = array_of_len(cx, value, path, 4)?;
+  Ok(vec4!(q[0], q[1], q[2], q[3]))
+}
+
+/// Accepts either a bare projection name or `{ type, ... }` with its
+/// parameters: `"perspective"`, `"orthographic"` (`height`), `"fisheye"`
+/// (`fov`, in radians) or `"equirectangular"`
+pub fn projection_from_js<'a, C: Context<'a>>(cx: &mut C, value: Handle<'a, JsValue>, path: &str) -> NeonResult<Projection> {
+  if is_absent(value) {
+    return Ok(Projection::Perspective);
+  }
+  let (ty, params) = typed_descriptor(cx, value, path)?;
+  match ty.as_str() {
+    "perspective" => {
+      check_fields(cx, params, path, &["type"])?;
+      Ok(Projection::Perspective)
+    },
+    "orthographic" => {
+      check_fields(cx, params, path, &["type", "height"])?;
+      let height = positive_or(cx, params, path, "height", 2.0)?;
+      Ok(Projection::Orthographic { height })
+    },
+    "fisheye" => {
+      check_fields(cx, params, path, &["type", "fov"])?;
+      let fov = positive_or(cx, params, path, "fov", PI)?;
+      Ok(Projection::Fisheye { fov })
+    },
+    "equirectangular" => {
+      check_fields(cx, params, path, &["type"])?;
+      Ok(Projection::Equirectangular)
+    },
+    _ => unknown_type(cx, path, &ty, "perspective, orthographic, fisheye or equirectangular")
+  }
+}
+
+/// Reads a third person camera `{ target, azimuth, incline, distance }`
+/// with optional `projection`, `fovy` and lens settings `{ aperture,
+/// focalDistance, apertureBlades, apertureRotation }`. The focal distance
+/// defaults to the target distance.
+pub fn camera_from_js<'a, C: Context<'a>>(cx: &mut C, camera: Handle<'a, JsObject>) -> NeonResult<Camera> {
+  let path = "camera";
+  check_fields(cx, camera, path, &[
+    "target", "azimuth", "incline", "distance", "projection", "fovy",
+    "aperture", "focalDistance", "apertureBlades", "apertureRotation",
+  ])?;
+
+  // The target is either `{ x, y, z }` or, as in scene files, `[x, y, z]`
+  let target_path = field(path, "target");
+  let target = camera.get(cx, "target")?;
+  let target = if is_absent(target) || target.is_a::<JsArray>() {
+    vector3_from_js(cx, target, &target_path, Vector3::zero())?
+  } else {
+    let target = object(cx, target, &target_path)?;
+    check_fields(cx, target, &target_path, &["x", "y", "z"])?;
+    let target_x = number_or(cx, target, &target_path, "x", 0.0)?;
+    let target_y = number_or(cx, target, &target_path, "y", 0.0)?;
+    let target_z = number_or(cx, target, &target_path, "z", 0.0)?;
+    vec3!(target_x, target_y, target_z)
+  };
+  let azimuth = number_or(cx, camera, path, "azimuth", 0.0)?;
+  let incline = number_or(cx, camera, path, "incline", 0.0)?;
+  let distance = number_or(cx, camera, path, "distance", 0.0)?;
+  let tpc = ThirdPersonCamera {
+    target,
+    azimuth,
+    incline,
+    distance,
+  };
+  let mut result = Camera::third_person(&tpc);
+  let projection = camera.get(cx, "projection")?;
+  result.projection = projection_from_js(cx, projection, &field(path, "projection"))?;
+  result.fovy = positive_or(cx, camera, path, "fovy", result.fovy)?;
+  if result.fovy >= PI {
+    return cx.throw_range_error(format!("Expected {} to be less than pi, got {}", field(path, "fovy"), result.fovy));
+  }
+  result.aperture = number_or(cx, camera, path, "aperture", 0.0)?;
+  if result.aperture < 0.0 {
+    return cx.throw_range_error(format!("Expected {} to be non-negative, got {}", field(path, "aperture"), result.aperture));
+  }
+  let focal_distance = camera.get(cx, "focalDistance")?;
+  if !is_absent(focal_distance) {
+    result.focal_distance = positive_or(cx, camera, path, "focalDistance", result.focal_distance)?;
+  }
+  result.aperture_blades = count_or(cx, camera, path, "apertureBlades", 0)? as u32;
+  result.aperture_rotation = number_or(cx, camera, path, "apertureRotation", 0.0)?;
+  Ok(result)
+}
+
+pub fn transform_from_js<'a, C: Context<'a>>(cx: &mut C, transform: Handle<'a, JsObject>, path: &str) -> NeonResult<Transform> {
+  check_fields(cx, transform, path, &["position", "scale", "rotation"])?;
+  let position = transform.get(cx, "position")?;
+  let position = vector3_from_js(cx, position, &field(path, "position"), vec3!(0.0))?;
+  let scale = transform.get(cx, "scale")?;
+  let scale = vector3_from_js(cx, scale, &field(path, "scale"), vec3!(1.0))?;
+  let rotation = transform.get(cx, "rotation")?;
+  let rotation = quaternion_from_js(cx, rotation, &field(path, "rotation"))?;
+  let transform = Transform { position, scale, rotation };
+  if let Err((key, expectation)) = transform.check() {
+    return cx.throw_range_error(format!("Expected {} to be {}", field(path, key), expectation));
+  }
+  Ok(transform)
+}
+
+/// Accepts either a bare type name (`"sphere"`), an object carrying the
+/// type together with its parameters (`{ type: "sphere", radius: 0.3 }`) or
+/// a native `Mesh`. Instances, `{ type: "instance", prototype, transform }`,
+/// name one of `prototypes`.
+pub fn intersectable_from_js<'a, C: Context<'a>>(
+  cx: &mut C,
+  value: Handle<'a, JsValue>,
+  path: &str,
+  prototypes: &[Arc<Prototype>]
+) -> NeonResult<Box<dyn Intersectable + Send>> {
+  if let Ok(mesh) = value.downcast::<JsMesh>() {
+    return Ok(Box::new(cx.borrow(&mesh, |mesh| mesh.clone())));
+  }
+  if is_absent(value) {
+    return expected(cx, path, "a type name, a descriptor or a Mesh", value);
+  }
+  let (ty, params) = typed_descriptor(cx, value, path)?;
+  match ty.as_str() {
+    "sphere" => {
+      check_fields(cx, params, path, &["type", "radius"])?;
+      let radius = positive_or(cx, params, path, "radius", 0.5)?;
+      Ok(Box::new(Sphere::new(radius)))
+    },
+    "cube" => {
+      check_fields(cx, params, path, &["type", "size"])?;
+      let size = params.get(cx, "size")?;
+      let size = vector3_from_js(cx, size, &field(path, "size"), vec3!(1.0))?;
+      Ok(Box::new(Cube::new(size.x, size.y, size.z)))
+    },
+    "plane" => {
+      check_fields(cx, params, path, &["type"])?;
+      Ok(Box::new(Plane::new()))
+    },
+    "mesh" => Ok(Box::new(mesh_from_js(cx, params, path)?)),
+    "instance" => {
+      check_fields(cx, params, path, &["type", "prototype", "transform"])?;
+      let name = params.get(cx, "prototype")?;
+      let name = match name.downcast::<JsString>() {
+        Ok(name) => name.value(),
+        Err(_) => return expected(cx, &field(path, "prototype"), "a prototype name", name)
+      };
+      let prototype = match prototypes.iter().find(|prototype| prototype.name == name) {
+        Some(prototype) => Arc::clone(prototype),
+        None => return cx.throw_range_error(format!("No prototype named \"{}\" for {}", name, field(path, "prototype")))
+      };
+      let transform = node_transform_from_js(cx, params, path)?;
+      Ok(Box::new(Instance::new(prototype, transform)))
+    },
+    _ => unknown_type(cx, path, &ty, "sphere, cube, plane, mesh or instance")
+  }
+}
+
+/// Accepts `{ type: "diffuse" | "mirror" | "glossy" | "glass", ... }` along
+/// with the parameters of the given material type, or a native `Material`
+pub fn material_from_js<'a, C: Context<'a>>(cx: &mut C, params: Handle<'a, JsObject>, path: &str) -> NeonResult<Box<dyn Material + Send>> {
+  if let Ok(material) = params.downcast::<JsMaterial>() {
+    return Ok(cx.borrow(&material, |material| material.clone()));
+  }
+  let ty = string_or(cx, params, path, "type", "")?;
+  match ty.as_str() {
+    "diffuse" => {
+      check_fields(cx, params, path, &["type", "albedo"])?;
+      let albedo = params.get(cx, "albedo")?;
+      let albedo = vector3_from_js(cx, albedo, &field(path, "albedo"), vec3!(0.8))?;
+      Ok(Box::new(Lambertian::new(albedo)))
+    },
+    "mirror" => {
+      check_fields(cx, params, path, &["type", "reflectance"])?;
+      let reflectance = params.get(cx, "reflectance")?;
+      let reflectance = vector3_from_js(cx, reflectance, &field(path, "reflectance"), vec3!(1.0))?;
+      Ok(Box::new(Mirror::new(reflectance)))
+    },
+    "glossy" => {
+      check_fields(cx, params, path, &["type", "diffuse", "specular", "exponent"])?;
+      let diffuse = params.get(cx, "diffuse")?;
+      let diffuse = vector3_from_js(cx, diffuse, &field(path, "diffuse"), vec3!(0.5))?;
+      let specular = params.get(cx, "specular")?;
+      let specular = vector3_from_js(cx, specular, &field(path, "specular"), vec3!(0.3))?;
+      let exponent = positive_or(cx, params, path, "exponent", 32.0)?;
+      Ok(Box::new(Glossy::new(diffuse, specular, exponent)))
+    },
+    "glass" => {
+      check_fields(cx, params, path, &["type", "ior", "tint"])?;
+      let ior = positive_or(cx, params, path, "ior", 1.5)?;
+      let tint = params.get(cx, "tint")?;
+      let tint = vector3_from_js(cx, tint, &field(path, "tint"), vec3!(1.0))?;
+      Ok(Box::new(Dielectric::new(ior, tint)))
+    },
+    _ => unknown_type(cx, path, &ty, "diffuse, mirror, glossy or glass")
+  }
+}
+
+/// Accepts `{ type: "point" | "directional" | "spot" | "sphere", ... }` along
+/// with the parameters of the given light type. Angles are in radians.
+pub fn light_from_js<'a, C: Context<'a>>(cx: &mut C, params: Handle<'a, JsObject>) -> NeonResult<Box<dyn Light + Send>> {
+  let path = "light";
+  let ty = string_or(cx, params, path, "type", "")?;
+  match ty.as_str() {
+    "point" => {
+      check_fields(cx, params, path, &["type", "position", "intensity"])?;
+      let position = params.get(cx, "position")?;
+      let position = vector3_from_js(cx, position, &field(path, "position"), vec3!(0.0))?;
+      let intensity = params.get(cx, "intensity")?;
+      let intensity = vector3_from_js(cx, intensity, &field(path, "intensity"), vec3!(1.0))?;
+      Ok(Box::new(PointLight::new(position, intensity)))
+    },
+    "directional" => {
+      check_fields(cx, params, path, &["type", "direction", "irradiance"])?;
+      let direction = params.get(cx, "direction")?;
+      let direction = vector3_from_js(cx, direction, &field(path, "direction"), -Vector3::j())?;
+      let irradiance = params.get(cx, "irradiance")?;
+      let irradiance = vector3_from_js(cx, irradiance, &field(path, "irradiance"), vec3!(1.0))?;
+      Ok(Box::new(DirectionalLight::new(direction, irradiance)))
+    },
+    "spot" => {
+      check_fields(cx, params, path, &["type", "position", "direction", "intensity", "cutoff", "falloffStart"])?;
+      let position = params.get(cx, "position")?;
+      let position = vector3_from_js(cx, position, &field(path, "position"), vec3!(0.0))?;
+      let direction = params.get(cx, "direction")?;
+      let direction = vector3_from_js(cx, direction, &field(path, "direction"), -Vector3::j())?;
+      let intensity = params.get(cx, "intensity")?;
+      let intensity = vector3_from_js(cx, intensity, &field(path, "intensity"), vec3!(1.0))?;
+      let cutoff = positive_or(cx, params, path, "cutoff", 0.5)?;
+      let falloff_start = number_or(cx, params, path, "falloffStart", cutoff * 0.8)?;
+      Ok(Box::new(SpotLight::new(position, direction, intensity, cutoff, falloff_start)))
+    },
+    "sphere" => {
+      check_fields(cx, params, path, &["type", "center", "radius", "radiance"])?;
+      let center = params.get(cx, "center")?;
+      let center = vector3_from_js(cx, center, &field(path, "center"), vec3!(0.0))?;
+      let radius = positive_or(cx, params, path, "radius", 0.1)?;
+      let radiance = params.get(cx, "radiance")?;
+      let radiance = vector3_from_js(cx, radiance, &field(path, "radiance"), vec3!(1.0))?;
+      Ok(Box::new(SphereLight::new(center, radius, radiance)))
+    },
+    _ => unknown_type(cx, path, &ty, "point, directional, spot or sphere")
+  }
+}
+
+/// Optional transform of an object or group descriptor
+fn node_transform_from_js<'a, C: Context<'a>>(cx: &mut C, descriptor: Handle<'a, JsObject>, path: &str) -> NeonResult<Transform> {
+  let transform = descriptor.get(cx, "transform")?;
+  if is_absent(transform) {
+    return Ok(Transform::identity());
+  }
+  let transform_path = field(path, "transform");
+  let transform = object(cx, transform, &transform_path)?;
+  transform_from_js(cx, transform, &transform_path)
+}
+
+/// Optional `parent` group id of an object or group descriptor. Whether the
+/// group exists is up to the scene to check.
+fn parent_from_js<'a, C: Context<'a>>(cx: &mut C, descriptor: Handle<'a, JsObject>, path: &str) -> NeonResult<Option<usize>> {
+  let parent = descriptor.get(cx, "parent")?;
+  if is_absent(parent) {
+    Ok(None)
+  } else {
+    count(cx, parent, &field(path, "parent")).map(Some)
+  }
+}
+
+/// Converts an `{ name, parent, transform, intersectable, material }`
+/// descriptor into a scene object, `parent` being the id of the group it
+/// hangs from and `transform` relative to that group. Objects without a
+/// material get a grey diffuse one, and the name and parent are optional.
+/// The id is left for the scene to assign, and instances may refer to any of
+/// `prototypes`.
+pub fn object_from_js<'a, C: Context<'a>>(
+  cx: &mut C,
+  descriptor: Handle<'a, JsObject>,
+  prototypes: &[Arc<Prototype>]
+) -> NeonResult<RenderObject> {
+  let path = "object";
+  check_fields(cx, descriptor, path, &["name", "parent", "transform", "intersectable", "material"])?;
+  let transform = node_transform_from_js(cx, descriptor, path)?;
+  let parent = parent_from_js(cx, descriptor, path)?;
+  let intersectable = descriptor.get(cx, "intersectable")?;
+  let intersectable = intersectable_from_js(cx, intersectable, &field(path, "intersectable"), prototypes)?;
+  let material = descriptor.get(cx, "material")?;
+  let material = if is_absent(material) {
+    Box::new(Lambertian::new(vec3!(0.8)))
+  } else {
+    let material_path = field(path, "material");
+    let material = object(cx, material, &material_path)?;
+    material_from_js(cx, material, &material_path)?
+  };
+  let name = descriptor.get(cx, "name")?;
+  let name = if is_absent(name) { None } else { Some(string_or(cx, descriptor, path, "name", "")?) };
+  Ok(RenderObject { id: 0, name, parent, transform, intersectable, material })
+}
+
+/// Converts a `{ name, parts }` descriptor into a prototype, each part being
+/// a `{ transform, intersectable, material }` object. Parts without a
+/// material take the one of the object holding each instance, and may be
+/// instances of earlier `prototypes` themselves.
+pub fn prototype_from_js<'a, C: Context<'a>>(
+  cx: &mut C,
+  descriptor: Handle<'a, JsObject>,
+  prototypes: &[Arc<Prototype>]
+) -> NeonResult<Prototype> {
+  let path = "prototype";
+  check_fields(cx, descriptor, path, &["name", "parts"])?;
+  let name = descriptor.get(cx, "name")?;
+  let name = match name.downcast::<JsString>() {
+    Ok(name) => name.value(),
+    Err(_) => return expected(cx, &field(path, "name"), "a string", name)
+  };
+  let parts = descriptor.get(cx, "parts")?;
+  let parts = match parts.downcast::<JsArray>() {
+    Ok(parts) => parts,
+    Err(_) => return expected(cx, &field(path, "parts"), "an array of parts", parts)
+  };
+  let mut result = Vec::with_capacity(parts.len() as usize);
+  for i in 0..parts.len() {
+    let part_path = format!("{}.parts[{}]", path, i);
+    let part = parts.get(cx, i)?;
+    let part = object(cx, part, &part_path)?;
+    check_fields(cx, part, &part_path, &["transform", "intersectable", "material"])?;
+    let transform = node_transform_from_js(cx, part, &part_path)?;
+    let intersectable = part.get(cx, "intersectable")?;
+    let intersectable = intersectable_from_js(cx, intersectable, &field(&part_path, "intersectable"), prototypes)?;
+    let material = part.get(cx, "material")?;
+    let material = if is_absent(material) {
+      None
+    } else {
+      let material_path = field(&part_path, "material");
+      let material = object(cx, material, &material_path)?;
+      Some(material_from_js(cx, material, &material_path)?)
+    };
+    result.push(PrototypePart::new(transform, intersectable, material));
+  }
+  Ok(Prototype::new(name, result))
+}
+
+/// Converts a `{ name, parent, transform }` descriptor into a group, every
+/// field being optional. The id is left for the scene to assign.
+pub fn group_from_js<'a, C: Context<'a>>(cx: &mut C, descriptor: Handle<'a, JsObject>) -> NeonResult<Group> {
+  let path = "group";
+  check_fields(cx, descriptor, path, &["name", "parent", "transform"])?;
+  let transform = node_transform_from_js(cx, descriptor, path)?;
+  let parent = parent_from_js(cx, descriptor, path)?;
+  let name = descriptor.get(cx, "name")?;
+  let name = if is_absent(name) { None } else { Some(string_or(cx, descriptor, path, "name", "")?) };
+  Ok(Group { id: 0, name, parent, transform })
+}
+
+/// Float32 data of each AOV, keyed by name, in `ArrayBuffer`s that the JS
+/// side wraps into `Float32Array`s
+pub fn aovs_to_js<'a, C: Context<'a>>(cx: &mut C, buffers: &[AovBuffer]) -> JsResult<'a, JsObject> {
+  let result = cx.empty_object();
+  for buffer in buffers {
+    let mut array_buffer = JsArrayBuffer::new(cx, (buffer.data.len() * 4) as u32)?;
+    cx.borrow_mut(&mut array_buffer, |data| data.as_mut_slice::<f32>().copy_from_slice(&buffer.data));
+    result.set(cx, buffer.aov.name(), array_buffer)?;
+  }
+  Ok(result)
+}
+
+/// Reads the render options, which take the same fields as the render
+/// settings of scene files (the `width` and `height` being ignored)
+pub fn options_from_js<'a, C: Context<'a>>(cx: &mut C, options: Option<Handle<'a, JsValue>>) -> NeonResult<RenderOptions> {
+  let path = "options";
+  let mut fields = match options {
+    Some(options) if !is_absent(options) => {
+      let options = object(cx, options, path)?;
+      object_to_json(cx, options, path)?
+    },
+    _ => Map::new()
+  };
+
+  // A filter may be given by name alone, as everywhere in the JS bindings
+  if let Some(Value::String(name)) = fields.get("filter").cloned() {
+    let mut filter = Map::new();
+    filter.insert(String::from("type"), Value::String(name));
+    fields.insert(String::from("filter"), Value::Object(filter));
+  }
+
+  let settings: RenderSettings = match serde_json::from_value(Value::Object(fields.clone())) {
+    Ok(settings) => settings,
+    Err(err) => {
+      // Deserialize the fields one at a time to name the offending one
+      let key = fields.iter().map(|(key, _)| key).find(|&key| {
+        let field = fields.iter().filter(|&(other, _)| other == key).map(|(key, value)| (key.clone(), value.clone())).collect();
+        serde_json::from_value::<RenderSettings>(Value::Object(field)).is_err()
+      });
+      let path = key.map_or_else(|| path.to_string(), |key| field(path, key));
+      return cx.throw_type_error(format!("Invalid {}: {}", path, err));
+    }
+  };
+  settings.to_options().or_else(|err| match err {
+    SettingsError::UnknownIntegrator(name) => cx.throw_type_error(format!(
+      "Unknown options.integrator \"{}\", expected normal, depth, ao, whitted or path", name
+    )),
+    SettingsError::OutOfRange(key, expectation) => {
+      cx.throw_range_error(format!("Expected {} to be {}", field(path, key), expectation))
+    },
+  })
+}
+
+/// Own fields of a plain data object as JSON, leaving out the `undefined`
+/// and `null` ones so that they take their default
+fn object_to_json<'a, C: Context<'a>>(cx: &mut C, obj: Handle<'a, JsObject>, path: &str) -> NeonResult<Map<String, Value>> {
+  let mut fields = Map::new();
+  let keys = obj.get_own_property_names(cx)?;
+  for i in 0..keys.len() {
+    if let Ok(key) = keys.get(cx, i)?.downcast::<JsString>() {
+      let key = key.value();
+      let value = obj.get(cx, key.as_str())?;
+      if !is_absent(value) {
+        let value = js_to_json(cx, value, &field(path, &key))?;
+        fields.insert(key, value);
+      }
+    }
+  }
+  Ok(fields)
+}
+
+fn js_to_json<'a, C: Context<'a>>(cx: &mut C, value: Handle<'a, JsValue>, path: &str) -> NeonResult<Value> {
+  if let Ok(b) = value.downcast::<JsBoolean>() {
+    Ok(Value::Bool(b.value()))
+  } else if let Ok(n) = value.downcast::<JsNumber>() {
+    // Integral numbers stay integers for the `usize` fields to accept them
+    let n = n.value();
+    if n.fract() == 0.0 && n.abs() <= u32::MAX as f64 {
+      Ok(Value::from(n as i64))
+    } else {
+      match Number::from_f64(n) {
+        Some(n) => Ok(Value::Number(n)),
+        None => cx.throw_range_error(format!("Expected {} to be finite, got {}", path, n))
+      }
+    }
+  } else if let Ok(s) = value.downcast::<JsString>() {
+    Ok(Value::String(s.value()))
+  } else if let Ok(arr) = value.downcast::<JsArray>() {
+    let mut elements = Vec::with_capacity(arr.len() as usize);
+    for i in 0..arr.len() {
+      let element = arr.get(cx, i)?;
+      elements.push(js_to_json(cx, element, &format!("{}[{}]", path, i))?);
+    }
+    Ok(Value::Array(elements))
+  } else if is_absent(value) {
+    Ok(Value::Null)
+  } else {
+    let obj = object(cx, value, path)?;
+    Ok(Value::Object(object_to_json(cx, obj, path)?))
+  }
+}
+
+/// Builds the JS counterpart of a JSON value, e.g. a serialized scene
+pub fn json_to_js<'a, C: Context<'a>>(cx: &mut C, value: &Value) -> JsResult<'a, JsValue> {
+  Ok(match value {
+    Value::Null => cx.null().upcast(),
+    Value::Bool(b) => cx.boolean(*b).upcast(),
+    Value::Number(n) => cx.number(n.as_f64().unwrap_or(0.0)).upcast(),
+    Value::String(s) => cx.string(s).upcast(),
+    Value::Array(elements) => {
+      let arr = JsArray::new(cx, elements.len() as u32);
+      for (i, element) in elements.iter().enumerate() {
+        let element = json_to_js(cx, element)?;
+        arr.set(cx, i as u32, element)?;
+      }
+      arr.upcast()
+    },
+    Value::Object(fields) => {
+      let obj = cx.empty_object();
+      for (key, field) in fields {
+        let field = json_to_js(cx, field)?;
+        obj.set(cx, key.as_str(), field)?;
+      }
+      obj.upcast()
+    },
+  })
+}
@@ -0,0 +1,81 @@
+use std::sync::Arc;
+
+use ::math::{Vector3, Spectrum};
+use ::util::ImageData;
+use ::filter::Filter;
+use ::tonemap::ToneMapper;
+
+/// Full precision linear RGB image the samples get splatted into. Each pixel holds the
+/// filter weighted sum of the radiance of nearby samples along with the sum
+/// of the weights.
+#[derive(Clone)]
+pub struct Film {
+  pub width: usize,
+  pub height: usize,
+  filter: Arc<dyn Filter>,
+  sums: Vec<Spectrum>,
+  weights: Vec<f32>,
+}
+
+impl Film {
+  pub fn new(width: usize, height: usize, filter: Arc<dyn Filter>) -> Self {
+    Self {
+      width,
+      height,
+      filter,
+      sums: vec![Vector3::zero(); width * height],
+      weights: vec![0.0; width * height],
+    }
+  }
+
+  /// Rows above and below a sample that its splat may reach
+  pub fn row_radius(&self) -> usize {
+    (self.filter.radius().1 + 0.5).ceil() as usize
+  }
+
+  /// Add a sample taken at the continuous film position `(x, y)` to every
+  /// pixel whose center lies within the filter support
+  pub fn add_sample(&mut self, x: f32, y: f32, radiance: Spectrum) {
+    // A single infinite or NaN sample would poison every pixel it touches,
+    // turning black where the filter weight is zero
+    if !(radiance.x.is_finite() && radiance.y.is_finite() && radiance.z.is_finite()) {
+      return;
+    }
+    let (rx, ry) = self.filter.radius();
+    let x0 = (x - 0.5 - rx).ceil().max(0.0) as usize;
+    let y0 = (y - 0.5 - ry).ceil().max(0.0) as usize;
+    let x1 = ((x - 0.5 + rx).floor() as isize).min(self.width as isize - 1);
+    let y1 = ((y - 0.5 + ry).floor() as isize).min(self.height as isize - 1);
+    if x1 < 0 || y1 < 0 {
+      return;
+    }
+    for py in y0..=(y1 as usize) {
+      for px in x0..=(x1 as usize) {
+        let weight = self.filter.evaluate(px as f32 + 0.5 - x, py as f32 + 0.5 - y);
+        let index = py * self.width + px;
+        self.sums[index] = self.sums[index] + radiance * weight;
+        self.weights[index] += weight;
+      }
+    }
+  }
+
+  /// Reconstructed radiance of a pixel
+  pub fn pixel(&self, x: usize, y: usize) -> Spectrum {
+    let index = y * self.width + x;
+    let weight = self.weights[index];
+    if weight > 0.0 {
+      (self.sums[index] / weight).max(&Vector3::zero())
+    } else {
+      Vector3::zero()
+    }
+  }
+
+  /// Tone map every pixel into the 8 bit `img_data`
+  pub fn write_image(&self, img_data: &mut ImageData, tone_mapper: &ToneMapper) {
+    for y in 0..self.height {
+      for x in 0..self.width {
+        img_data.set_pixel(x, y, &tone_mapper.map(self.pixel(x, y), x, y));
+      }
+    }
+  }
+}
@@ -0,0 +1,151 @@
+use std::sync::Arc;
+
+/// Pixel reconstruction filter, centered on the origin and measured in
+/// pixels
+pub trait Filter: Send + Sync {
+
+  /// Half extent of the support along x and y
+  fn radius(&self) -> (f32, f32);
+
+  fn evaluate(&self, x: f32, y: f32) -> f32;
+}
+
+/// Equal weight over the support. A radius of 0.5 averages the samples
+/// falling inside each pixel.
+pub struct BoxFilter {
+  pub radius: f32,
+}
+
+impl Filter for BoxFilter {
+  fn radius(&self) -> (f32, f32) {
+    (self.radius, self.radius)
+  }
+
+  fn evaluate(&self, _x: f32, _y: f32) -> f32 {
+    1.0
+  }
+}
+
+/// Weight falling off linearly to zero at the radius
+pub struct TentFilter {
+  pub radius: f32,
+}
+
+impl Filter for TentFilter {
+  fn radius(&self) -> (f32, f32) {
+    (self.radius, self.radius)
+  }
+
+  fn evaluate(&self, x: f32, y: f32) -> f32 {
+    (self.radius - x.abs()).max(0.0) * (self.radius - y.abs()).max(0.0)
+  }
+}
+
+/// Gaussian of falloff `alpha`, shifted down so that it reaches zero at the
+/// radius
+pub struct GaussianFilter {
+  pub radius: f32,
+  pub alpha: f32,
+}
+
+impl GaussianFilter {
+  fn gaussian(&self, d: f32) -> f32 {
+    ((-self.alpha * d * d).exp() - (-self.alpha * self.radius * self.radius).exp()).max(0.0)
+  }
+}
+
+impl Filter for GaussianFilter {
+  fn radius(&self) -> (f32, f32) {
+    (self.radius, self.radius)
+  }
+
+  fn evaluate(&self, x: f32, y: f32) -> f32 {
+    self.gaussian(x) * self.gaussian(y)
+  }
+}
+
+/// Mitchell-Netravali cubic. `b = c = 1/3` is the recommended trade-off
+/// between blurring and ringing.
+pub struct MitchellFilter {
+  pub radius: f32,
+  pub b: f32,
+  pub c: f32,
+}
+
+impl MitchellFilter {
+  /// The cubic over `[-2, 2]`
+  fn mitchell(&self, x: f32) -> f32 {
+    let (b, c) = (self.b, self.c);
+    let x = x.abs();
+    if x < 1.0 {
+      ((12.0 - 9.0 * b - 6.0 * c) * x * x * x + (-18.0 + 12.0 * b + 6.0 * c) * x * x + (6.0 - 2.0 * b)) / 6.0
+    } else if x < 2.0 {
+      ((-b - 6.0 * c) * x * x * x + (6.0 * b + 30.0 * c) * x * x + (-12.0 * b - 48.0 * c) * x + (8.0 * b + 24.0 * c)) / 6.0
+    } else {
+      0.0
+    }
+  }
+}
+
+impl Filter for MitchellFilter {
+  fn radius(&self) -> (f32, f32) {
+    (self.radius, self.radius)
+  }
+
+  fn evaluate(&self, x: f32, y: f32) -> f32 {
+    self.mitchell(2.0 * x / self.radius) * self.mitchell(2.0 * y / self.radius)
+  }
+}
+
+/// Serializable form of the filters, e.g. `{ "type": "gaussian", "radius": 1.5 }`.
+/// Parameters left out take the same defaults as the JS render options.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+pub enum FilterDescriptor {
+  Box {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    radius: Option<f32>,
+  },
+  Tent {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    radius: Option<f32>,
+  },
+  Gaussian {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    radius: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    alpha: Option<f32>,
+  },
+  Mitchell {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    radius: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    b: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    c: Option<f32>,
+  },
+}
+
+impl Default for FilterDescriptor {
+  fn default() -> Self {
+    FilterDescriptor::Box { radius: None }
+  }
+}
+
+impl FilterDescriptor {
+  pub fn build(&self) -> Arc<dyn Filter> {
+    match *self {
+      FilterDescriptor::Box { radius } => Arc::new(BoxFilter { radius: radius.unwrap_or(0.5) }),
+      FilterDescriptor::Tent { radius } => Arc::new(TentFilter { radius: radius.unwrap_or(1.0) }),
+      FilterDescriptor::Gaussian { radius, alpha } => Arc::new(GaussianFilter {
+        radius: radius.unwrap_or(1.5),
+        alpha: alpha.unwrap_or(2.0),
+      }),
+      FilterDescriptor::Mitchell { radius, b, c } => Arc::new(MitchellFilter {
+        radius: radius.unwrap_or(2.0),
+        b: b.unwrap_or(1.0 / 3.0),
+        c: c.unwrap_or(1.0 / 3.0),
+      }),
+    }
+  }
+}
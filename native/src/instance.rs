@@ -0,0 +1,262 @@
+use std::sync::Arc;
+
+use ::math::Matrix4;
+use ::util::{Transform, Ray, Intersection, Placement};
+use ::intersectable::{Intersectable, ShapeDescriptor};
+use ::material::Material;
+use ::bounded::{Bounded, BoundingBox};
+use ::bvh::Bvh;
+
+/// Geometry shared by every instance of it, made of one or more placed
+/// shapes. The BVH over the parts is the bottom level of the two-level
+/// hierarchy, the scene BVH over objects and their instances the top one.
+pub struct Prototype {
+  /// Identifies the prototype within its scene, instances refer to it by
+  /// name in scene files
+  pub name: String,
+  pub parts: Vec<PrototypePart>,
+  bvh: Bvh,
+
+  /// Parts with infinite bounds, tested one by one
+  unbounded: Vec<usize>,
+}
+
+/// Shape of a prototype, placed in the space of the prototype
+#[derive(Clone)]
+pub struct PrototypePart {
+  transform: Transform,
+
+  /// `None` if the transform flattens the part, which is then never hit
+  placement: Option<Placement>,
+  pub intersectable: Box<dyn Intersectable + Send>,
+
+  /// Material of the part, `None` to take the one of the object holding
+  /// the instance so that instances may differ in color
+  pub material: Option<Box<dyn Material + Send>>,
+}
+
+impl PrototypePart {
+  pub fn new(transform: Transform, intersectable: Box<dyn Intersectable + Send>, material: Option<Box<dyn Material + Send>>) -> Self {
+    let matrix: Matrix4 = transform.into();
+    Self { transform, placement: Placement::new(matrix), intersectable, material }
+  }
+
+  pub fn transform(&self) -> Transform {
+    self.transform
+  }
+
+  fn intersect(&self, ray: &Ray) -> Option<Intersection<'_>> {
+    let placement = self.placement.as_ref()?;
+    self.intersectable.intersect(&ray.inverse_transform(placement)).map(|itsct| {
+      let itsct = itsct.transform(placement);
+      let t = (itsct.position - ray.origin).mag();
+      let material = self.material.as_ref().map(|material| &**material as &dyn Material);
+      Intersection { t, material: material.or(itsct.material), ..itsct }
+    })
+  }
+
+  fn bounding_box(&self) -> BoundingBox {
+    let bb = self.intersectable.bounding_box();
+    match self.placement {
+      Some(ref placement) if bb.is_finite() => bb.transform(placement.matrix),
+      Some(_) => bb,
+      None => BoundingBox::empty()
+    }
+  }
+}
+
+impl Prototype {
+  pub fn new(name: String, parts: Vec<PrototypePart>) -> Self {
+    let mut bounded = vec![];
+    let mut unbounded = vec![];
+    for (index, part) in parts.iter().enumerate() {
+      let bb = part.bounding_box();
+      if bb.is_finite() {
+        bounded.push((index, bb));
+      } else {
+        unbounded.push(index);
+      }
+    }
+    Self { name, parts, bvh: Bvh::new(bounded), unbounded }
+  }
+
+  /// Closest hit in the space of the prototype
+  pub fn intersect(&self, ray: &Ray) -> Option<Intersection<'_>> {
+    let closest = self.bvh.intersect(ray, |index| self.parts[index].intersect(ray));
+    self.unbounded.iter().fold(closest, |acc, &index| {
+      Intersection::min(acc, self.parts[index].intersect(ray))
+    })
+  }
+
+  pub fn bounding_box(&self) -> BoundingBox {
+    if self.unbounded.is_empty() {
+      self.bvh.bounding_box()
+    } else {
+      BoundingBox::infinite()
+    }
+  }
+
+  pub fn describe(&self) -> PrototypeDescriptor {
+    PrototypeDescriptor {
+      name: self.name.clone(),
+      parts: self.parts.iter().map(|part| PartDescriptor {
+        transform: part.transform,
+        intersectable: part.intersectable.describe(),
+        material: part.material.clone(),
+      }).collect(),
+    }
+  }
+}
+
+/// Serializable form of a prototype, e.g. `{ "name": "tree", "parts": [{
+/// "intersectable": { "type": "mesh", ... } }] }`
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PrototypeDescriptor {
+  pub name: String,
+  pub parts: Vec<PartDescriptor>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PartDescriptor {
+  #[serde(default)]
+  pub transform: Transform,
+  pub intersectable: ShapeDescriptor,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub material: Option<Box<dyn Material + Send>>,
+}
+
+impl PrototypeDescriptor {
+  /// Build the prototype, whose parts may be instances of `prototypes`.
+  /// `path` locates the descriptor in error messages.
+  pub fn build(self, prototypes: &[Arc<Prototype>], path: &str) -> Result<Prototype, String> {
+    let mut parts = Vec::with_capacity(self.parts.len());
+    for (index, part) in self.parts.into_iter().enumerate() {
+      let part_path = format!("{}.parts[{}]", path, index);
+      part.transform.check_at(&format!("{}.transform", part_path))?;
+      let intersectable = part.intersectable.build_with(prototypes, &format!("{}.intersectable", part_path))?;
+      parts.push(PrototypePart::new(part.transform, intersectable, part.material));
+    }
+    Ok(Prototype::new(self.name, parts))
+  }
+}
+
+/// Copy of a prototype placed by its own transform. Only the prototype
+/// pointer and the matrices are stored, so that memory grows with the number
+/// of instances rather than with the number of triangles.
+#[derive(Clone)]
+pub struct Instance {
+  pub prototype: Arc<Prototype>,
+  transform: Transform,
+
+  /// `None` if the transform flattens the instance, which is then never hit
+  placement: Option<Placement>,
+}
+
+impl Instance {
+  pub fn new(prototype: Arc<Prototype>, transform: Transform) -> Self {
+    let matrix: Matrix4 = transform.into();
+    Self { prototype, transform, placement: Placement::new(matrix) }
+  }
+
+  pub fn transform(&self) -> Transform {
+    self.transform
+  }
+}
+
+impl Intersectable for Instance {
+  fn intersect(&self, ray: &Ray) -> Option<Intersection<'_>> {
+    let placement = self.placement.as_ref()?;
+    self.prototype.intersect(&ray.inverse_transform(placement)).map(|itsct| {
+      let itsct = itsct.transform(placement);
+      let t = (itsct.position - ray.origin).mag();
+      Intersection { t, ..itsct }
+    })
+  }
+
+  fn describe(&self) -> ShapeDescriptor {
+    ShapeDescriptor::Instance { prototype: self.prototype.name.clone(), transform: self.transform }
+  }
+}
+
+impl Bounded for Instance {
+  fn bounding_box(&self) -> BoundingBox {
+    let bb = self.prototype.bounding_box();
+    match self.placement {
+      Some(ref placement) if bb.is_finite() => bb.transform(placement.matrix),
+      Some(_) => bb,
+      None => BoundingBox::empty()
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::f32::consts::PI;
+  use ::math::{Vector3, Quaternion};
+  use ::intersectable::{Sphere, Cube};
+  use ::material::Lambertian;
+  use ::object::Object;
+  use ::scene::Scene;
+
+  fn object(transform: Transform, intersectable: Box<dyn Intersectable + Send>) -> Object {
+    Object {
+      id: 0,
+      name: None,
+      parent: None,
+      transform,
+      intersectable,
+      material: Box::new(Lambertian::new(vec3!(0.5))),
+    }
+  }
+
+  #[test]
+  fn instance_hits_match_a_flat_copy() {
+    let sphere_transform = Transform { position: vec3!(0.5, 0.0, 0.0), ..Transform::identity() };
+    let cube_transform = Transform { position: vec3!(-0.5, 0.2, 0.3), scale: vec3!(1.0, 0.5, 2.0), ..Transform::identity() };
+    let prototype = Arc::new(Prototype::new(String::from("pair"), vec![
+      PrototypePart::new(sphere_transform, Box::new(Sphere::new(0.5)), None),
+      PrototypePart::new(cube_transform, Box::new(Cube::new(1.0, 1.0, 1.0)), None),
+    ]));
+
+    // A half turn about y commutes with the scale of the parts, so the
+    // instance composes with them into plain transforms
+    let rotation = Quaternion::axis_angle(vec3!(0.0, 1.0, 0.0), PI);
+    let instance_transform = Transform { position: vec3!(1.0, 2.0, 3.0), scale: vec3!(2.0), rotation };
+    let mut instanced = Scene::new();
+    instanced.add_object(object(Transform::identity(), Box::new(Instance::new(prototype, instance_transform))));
+    instanced.build_bvh();
+
+    let flat_part = |part: Transform| Transform {
+      position: instance_transform.position + vec3!(-part.position.x, part.position.y, -part.position.z) * 2.0,
+      scale: part.scale * 2.0,
+      rotation,
+    };
+    let mut flat = Scene::new();
+    flat.add_object(object(flat_part(sphere_transform), Box::new(Sphere::new(0.5))));
+    flat.add_object(object(flat_part(cube_transform), Box::new(Cube::new(1.0, 1.0, 1.0))));
+    flat.build_bvh();
+
+    let mut hits = 0;
+    for &direction in &[vec3!(0.0, 0.0, -1.0), vec3!(1.0, 0.0, 0.0), vec3!(0.3, -1.0, 0.2)] {
+      for i in 0..30 {
+        for j in 0..30 {
+          let offset = vec3!(i as f32 * 0.2 - 2.013, j as f32 * 0.2 - 1.029, i as f32 * 0.1 - 1.517);
+          let ray = Ray::new(vec3!(1.0, 2.0, 3.0) + offset - direction.normalize() * 10.0, direction.normalize());
+          match (instanced.intersect(&ray), flat.intersect(&ray)) {
+            (Some(a), Some(b)) => {
+              hits += 1;
+              assert!((a.t - b.t).abs() < 1e-3, "{} vs {}", a.t, b.t);
+              assert!((a.normal - b.normal).mag() < 1e-3);
+            },
+            (None, None) => {},
+            (a, b) => panic!("hit {} vs {} along {:?}", a.is_some(), b.is_some(), ray.direction),
+          }
+        }
+      }
+    }
+    assert!(hits > 300, "{} hits", hits);
+  }
+}
@@ -0,0 +1,217 @@
+use ::math::{Vector3, Spectrum, cosine_sample_hemisphere};
+use ::util::Ray;
+use ::scene::Scene;
+use ::sampler::Sampler;
+
+/// Estimates the radiance arriving along a camera ray
+pub trait Integrator: Send + Sync {
+  fn li(&self, ray: &Ray, scene: &Scene, sampler: &mut dyn Sampler) -> Spectrum;
+}
+
+/// Visualizes the world space normal, remapped from `[-1, 1]` to `[0, 1]`
+pub struct NormalIntegrator;
+
+impl Integrator for NormalIntegrator {
+  fn li(&self, ray: &Ray, scene: &Scene, _sampler: &mut dyn Sampler) -> Spectrum {
+    match scene.intersect(ray) {
+      Some(itsct) => itsct.normal * 0.5 + vec3!(0.5),
+      None => Vector3::zero()
+    }
+  }
+}
+
+/// Distance to the first hit, going from black at the camera to white at
+/// `max_distance`
+pub struct DepthIntegrator {
+  pub max_distance: f32,
+}
+
+impl Integrator for DepthIntegrator {
+  fn li(&self, ray: &Ray, scene: &Scene, _sampler: &mut dyn Sampler) -> Spectrum {
+    match scene.intersect(ray) {
+      Some(itsct) => vec3!((itsct.t / self.max_distance).min(1.0)),
+      None => vec3!(1.0)
+    }
+  }
+}
+
+/// Fraction of the cosine weighted hemisphere that is not blocked within
+/// `radius` of the first hit
+pub struct AmbientOcclusionIntegrator {
+  pub samples: usize,
+  pub radius: f32,
+}
+
+impl Integrator for AmbientOcclusionIntegrator {
+  fn li(&self, ray: &Ray, scene: &Scene, sampler: &mut dyn Sampler) -> Spectrum {
+    let itsct = match scene.intersect(ray) {
+      Some(itsct) => itsct,
+      None => return Vector3::zero()
+    };
+    let normal = if itsct.normal.dot(&ray.direction) > 0.0 { -itsct.normal } else { itsct.normal };
+    let samples = self.samples.max(1);
+    let unoccluded = (0..samples).filter(|_| {
+      let wi = cosine_sample_hemisphere(sampler.next_2d()).from_local(&normal);
+      !scene.occluded(&Ray::spawn(itsct.position, normal, wi), self.radius)
+    }).count();
+    vec3!(unoccluded as f32 / samples as f32)
+  }
+}
+
+/// Classic recursive ray tracer: direct lighting on every surface, plus
+/// perfect reflection and refraction through specular materials. Scenes
+/// without any light are previewed with a head light sitting at the camera.
+pub struct WhittedIntegrator {
+  pub max_depth: usize,
+}
+
+impl WhittedIntegrator {
+  fn trace(&self, ray: &Ray, scene: &Scene, sampler: &mut dyn Sampler, depth: usize) -> Spectrum {
+    let itsct = scene.intersect(ray);
+    if let Some((_, t, emitted)) = scene.intersect_lights(ray) {
+      if itsct.is_none_or(|itsct| t < itsct.t) {
+        return emitted;
+      }
+    }
+    let itsct = match itsct {
+      Some(itsct) => itsct,
+      None => return Vector3::zero()
+    };
+    let material = match itsct.material {
+      Some(material) => material,
+      None => return Vector3::zero()
+    };
+    if scene.lights.is_empty() {
+      let cos_theta = itsct.normal.dot(&ray.direction).abs();
+      return material.albedo() * cos_theta;
+    }
+
+    let wo = -ray.direction;
+    let mut radiance = scene.direct_lighting(&itsct, wo, sampler.next_2d());
+    if depth + 1 < self.max_depth {
+      for (wi, weight) in material.specular_directions(wo, itsct.normal) {
+        let next_ray = Ray::spawn(itsct.position, itsct.normal, wi);
+        radiance = radiance + weight * self.trace(&next_ray, scene, sampler, depth + 1);
+      }
+    }
+    radiance
+  }
+}
+
+impl Integrator for WhittedIntegrator {
+  fn li(&self, ray: &Ray, scene: &Scene, sampler: &mut dyn Sampler) -> Spectrum {
+    self.trace(ray, scene, sampler, 0)
+  }
+}
+
+/// Power heuristic with beta = 2 for combining two sampling strategies
+fn power_heuristic(pdf_f: f32, pdf_g: f32) -> f32 {
+  let f2 = pdf_f * pdf_f;
+  let g2 = pdf_g * pdf_g;
+  if f2 + g2 > 0.0 { f2 / (f2 + g2) } else { 0.0 }
+}
+
+/// Unidirectional path tracer with next event estimation. Every light is
+/// sampled once per vertex, and combined with BSDF sampling through
+/// multiple importance sampling.
+#[derive(Clone)]
+pub struct PathTracer {
+  pub max_depth: usize,
+
+  /// Depth after which paths get randomly terminated by Russian roulette
+  pub rr_depth: usize,
+}
+
+impl PathTracer {
+  pub fn new(max_depth: usize) -> Self {
+    Self { max_depth, rr_depth: 3 }
+  }
+}
+
+impl Integrator for PathTracer {
+  fn li(&self, camera_ray: &Ray, scene: &Scene, sampler: &mut dyn Sampler) -> Spectrum {
+    let mut radiance = Vector3::zero();
+    let mut throughput = vec3!(1.0);
+    let mut ray = Ray::new(camera_ray.origin, camera_ray.direction);
+
+    // State of the previous bounce, needed to weight emission found by BSDF
+    // sampling. Camera rays count as specular: there is no other strategy.
+    let mut specular_bounce = true;
+    let mut bsdf_pdf = 0.0;
+
+    for depth in 0..self.max_depth {
+      let itsct = scene.intersect(&ray);
+
+      // Emission of a light surface in front of the closest object
+      if let Some((index, t, emitted)) = scene.intersect_lights(&ray) {
+        if itsct.is_none_or(|itsct| t < itsct.t) {
+          let weight = if specular_bounce {
+            1.0
+          } else {
+            let light_pdf = scene.lights[index].pdf_li(ray.origin, ray.direction);
+            power_heuristic(bsdf_pdf, light_pdf)
+          };
+          radiance = radiance + throughput * emitted * weight;
+          break;
+        }
+      }
+
+      let itsct = match itsct {
+        Some(itsct) => itsct,
+        None => break
+      };
+      let material = match itsct.material {
+        Some(material) => material,
+        None => break
+      };
+      let (position, normal) = (itsct.position, itsct.normal);
+      let wo = -ray.direction;
+
+      // Next event estimation
+      if !material.is_specular() {
+        for light in scene.lights.iter() {
+          let u = sampler.next_2d();
+          let ls = match light.sample_li(position, u) {
+            Some(ls) => ls,
+            None => continue
+          };
+          let f = material.eval(wo, ls.wi, normal);
+          let cos_theta = ls.wi.dot(&normal).abs();
+          if f.max_component() <= 0.0 || cos_theta <= 0.0 || ls.pdf <= 0.0 {
+            continue;
+          }
+          if scene.occluded(&Ray::spawn(position, normal, ls.wi), ls.distance) {
+            continue;
+          }
+          let weight = if light.is_delta() {
+            1.0
+          } else {
+            power_heuristic(ls.pdf, material.pdf(wo, ls.wi, normal))
+          };
+          radiance = radiance + throughput * f * ls.radiance * (cos_theta * weight / ls.pdf);
+        }
+      }
+
+      // Continue the path by sampling the BSDF
+      let bs = match material.sample(wo, normal, sampler.next_2d()) {
+        Some(bs) => bs,
+        None => break
+      };
+      throughput = throughput * bs.weight;
+      specular_bounce = bs.is_specular;
+      bsdf_pdf = bs.pdf;
+      ray = Ray::spawn(position, normal, bs.wi);
+
+      // Russian roulette
+      if depth + 1 >= self.rr_depth {
+        let survival = throughput.max_component().min(0.95);
+        if survival <= 0.0 || sampler.next_1d() >= survival {
+          break;
+        }
+        throughput = throughput / survival;
+      }
+    }
+
+    radiance
+  }
+}
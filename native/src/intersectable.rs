@@ -1,8 +1,90 @@
+use serde::{Serialize, Serializer, Deserialize, Deserializer};
+use serde::de::Error;
+use std::sync::Arc;
+
 use ::math::{Vector3};
-use ::util::{Ray, Intersection};
+use ::util::{Ray, Intersection, Transform};
+use ::bounded::Bounded;
+use ::mesh::TriangleMesh;
+use ::instance::{Instance, Prototype};
+
+/// Shapes in their own object space. The bounding box is used to place
+/// them in the scene BVH. Shapes are shared by the render workers.
+pub trait Intersectable: IntersectableClone + Bounded + Sync {
+  fn intersect(&self, ray: &Ray) -> Option<Intersection<'_>>;
+
+  /// Parameters the shape can be rebuilt from
+  fn describe(&self) -> ShapeDescriptor;
+}
+
+/// Serializable form of the shapes, the same as the intersectable
+/// descriptors of the JS bindings, e.g. `{ "type": "sphere", "radius": 0.5 }`
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+pub enum ShapeDescriptor {
+  Sphere { radius: f32 },
+  Cube { size: Vector3 },
+  Plane,
+
+  /// Flat vertex arrays and triangle indices, see `TriangleMesh::from_flat`
+  Mesh {
+    positions: Vec<f32>,
+    #[serde(default)]
+    normals: Vec<f32>,
+    #[serde(default)]
+    uvs: Vec<f32>,
+    indices: Vec<u32>,
+  },
+
+  /// Copy of the prototype of the scene with the given name
+  Instance {
+    prototype: String,
+    #[serde(default)]
+    transform: Transform,
+  },
+}
+
+impl ShapeDescriptor {
+  pub fn build(self) -> Result<Box<dyn Intersectable + Send>, String> {
+    Ok(match self {
+      ShapeDescriptor::Sphere { radius } => Box::new(Sphere::new(radius)),
+      ShapeDescriptor::Cube { size } => Box::new(Cube::new(size.x, size.y, size.z)),
+      ShapeDescriptor::Plane => Box::new(Plane::new()),
+      ShapeDescriptor::Mesh { positions, normals, uvs, indices } => {
+        Box::new(TriangleMesh::from_flat(&positions, &normals, &uvs, &indices)?)
+      },
+      ShapeDescriptor::Instance { prototype, .. } => {
+        return Err(format!("instance of prototype \"{}\" outside of a scene", prototype));
+      },
+    })
+  }
+
+  /// Same as `build`, looking the prototypes of instances up in
+  /// `prototypes`. `path` locates the descriptor in error messages.
+  pub fn build_with(self, prototypes: &[Arc<Prototype>], path: &str) -> Result<Box<dyn Intersectable + Send>, String> {
+    match self {
+      ShapeDescriptor::Instance { prototype, transform } => {
+        transform.check_at(&format!("{}.transform", path))?;
+        match prototypes.iter().find(|candidate| candidate.name == prototype) {
+          Some(prototype) => Ok(Box::new(Instance::new(Arc::clone(prototype), transform))),
+          None => Err(format!("unknown prototype \"{}\"", prototype)),
+        }
+      },
+      descriptor => descriptor.build(),
+    }
+  }
+}
 
-pub trait Intersectable: IntersectableClone {
-  fn intersect(&self, ray: &Ray) -> Option<Intersection>;
+impl Serialize for Box<dyn Intersectable + Send> {
+  fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+    self.describe().serialize(serializer)
+  }
+}
+
+impl<'de> Deserialize<'de> for Box<dyn Intersectable + Send> {
+  fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+    ShapeDescriptor::deserialize(deserializer)?.build().map_err(D::Error::custom)
+  }
 }
 
 pub trait IntersectableClone {
@@ -35,7 +117,11 @@ impl Cube {
 }
 
 impl Intersectable for Cube {
-  fn intersect(&self, ray: &Ray) -> Option<Intersection> {
+  fn describe(&self) -> ShapeDescriptor {
+    ShapeDescriptor::Cube { size: vec3!(self.size_x, self.size_y, self.size_z) }
+  }
+
+  fn intersect(&self, ray: &Ray) -> Option<Intersection<'_>> {
     let hx = self.size_x / 2.0;
     let hy = self.size_y / 2.0;
     let hz = self.size_z / 2.0;
@@ -50,27 +136,29 @@ impl Intersectable for Cube {
     if t_max - t_min < 0.0 {
       None
     } else {
-      let (t, sign) = if t_min > 0.0 && t_max > 0.0 {
-        (t_min, 1.0)
+      let t = if t_min > 0.0 && t_max > 0.0 {
+        t_min
       } else if t_min < 0.0 && t_max > 0.0 {
-        (t_max, -1.0)
+        t_max
       } else {
         return None;
       };
       let position = ray.point_at(t);
+
+      // Outward facing normal of the face that was hit
       let normal = if t == t_min_vec.y || t == t_max_vec.y {
         if position.y > 0.0 { Vector3::j() } else { -Vector3::j() }
-      } else if t == t_min_vec.x || t == t_min_vec.x {
+      } else if t == t_min_vec.x || t == t_max_vec.x {
         if position.x > 0.0 { Vector3::i() } else { -Vector3::i() }
       } else {
         if position.z > 0.0 { Vector3::k() } else { -Vector3::k() }
-      } * sign;
-      Some(Intersection { position, normal, t })
+      };
+      Some(Intersection { position, normal, t, uv: None, material: None, object: None })
     }
   }
 }
 
-#[derive(Clone)]
+#[derive(Clone, Default)]
 pub struct Plane;
 
 impl Plane {
@@ -80,7 +168,11 @@ impl Plane {
 }
 
 impl Intersectable for Plane {
-  fn intersect(&self, ray: &Ray) -> Option<Intersection> {
+  fn describe(&self) -> ShapeDescriptor {
+    ShapeDescriptor::Plane
+  }
+
+  fn intersect(&self, ray: &Ray) -> Option<Intersection<'_>> {
     if ray.direction.y == 0.0 {
       None
     } else {
@@ -88,8 +180,11 @@ impl Intersectable for Plane {
       if t > 0.0 {
         Some(Intersection {
           position: ray.point_at(t),
-          normal: if ray.origin.y > 0.0 { Vector3::j() } else { -Vector3::j() },
-          t
+          normal: Vector3::j(),
+          t,
+          uv: None,
+          material: None,
+          object: None,
         })
       } else {
         None
@@ -110,25 +205,32 @@ impl Sphere {
 }
 
 impl Intersectable for Sphere {
-  fn intersect(&self, ray: &Ray) -> Option<Intersection> {
+  fn describe(&self) -> ShapeDescriptor {
+    ShapeDescriptor::Sphere { radius: self.radius }
+  }
+
+  fn intersect(&self, ray: &Ray) -> Option<Intersection<'_>> {
     let a = ray.direction.dot(&ray.direction);
     let b = 2.0 * ray.direction.dot(&ray.origin);
     let c = ray.origin.dot(&ray.origin) - self.radius * self.radius;
     let d = (b * b - 4.0 * a * c).sqrt();
     let t1 = (-b + d) / (2.0 * a);
     let t2 = (-b - d) / (2.0 * a);
-    let (t, sign) = if t1 > 0.0 && t2 > 0.0 {
-      (t1.min(t2), 1.0)
+    let t = if t1 > 0.0 && t2 > 0.0 {
+      t1.min(t2)
     } else if t1 * t2 < 0.0 {
-      (t1.max(t2), -1.0)
+      t1.max(t2)
     } else {
       return None
     };
     let position = ray.point_at(t);
     Some(Intersection {
-      position: position,
-      normal: (position * sign).normalize(),
-      t: t,
+      position,
+      normal: position.normalize(),
+      t,
+      uv: None,
+      material: None,
+      object: None,
     })
   }
 }
\ No newline at end of file
@@ -0,0 +1,316 @@
+use std::f32::consts::PI;
+
+use serde::{Serialize, Serializer, Deserialize, Deserializer};
+
+use ::math::{Vector3, Spectrum};
+use ::util::Ray;
+
+/// Incident illumination arriving at a shading point from a light
+pub struct LightSample {
+
+  /// Unit direction from the shading point towards the light
+  pub wi: Vector3,
+
+  /// Distance to the sampled point on the light, used to bound shadow rays
+  pub distance: f32,
+
+  /// Radiance arriving along `wi`, not yet divided by `pdf`
+  pub radiance: Spectrum,
+
+  /// Solid angle density of `wi`. Delta lights always report 1.
+  pub pdf: f32,
+}
+
+pub trait Light: LightClone + Sync {
+  fn sample_li(&self, position: Vector3, u: (f32, f32)) -> Option<LightSample>;
+
+  /// Solid angle density with which `sample_li` would produce `wi`
+  fn pdf_li(&self, _position: Vector3, _wi: Vector3) -> f32 {
+    0.0
+  }
+
+  /// Distance and emitted radiance if `ray` hits the light itself. Only
+  /// lights with a surface can be hit.
+  fn intersect(&self, _ray: &Ray) -> Option<(f32, Spectrum)> {
+    None
+  }
+
+  /// Whether the light is described by a delta distribution, i.e. it can
+  /// only be reached through `sample_li`
+  fn is_delta(&self) -> bool {
+    true
+  }
+
+  /// Parameters the light can be rebuilt from
+  fn describe(&self) -> LightDescriptor;
+}
+
+/// Serializable form of the lights, the same as the light descriptors of
+/// the JS bindings, e.g. `{ "type": "point", "position": [0, 2, 0], "intensity": [5, 5, 5] }`
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+pub enum LightDescriptor {
+  Point { position: Vector3, intensity: Spectrum },
+  Directional { direction: Vector3, irradiance: Spectrum },
+  #[serde(rename_all = "camelCase")]
+  Spot { position: Vector3, direction: Vector3, intensity: Spectrum, cutoff: f32, falloff_start: f32 },
+  Sphere { center: Vector3, radius: f32, radiance: Spectrum },
+}
+
+impl LightDescriptor {
+  pub fn build(self) -> Box<dyn Light + Send> {
+    match self {
+      LightDescriptor::Point { position, intensity } => Box::new(PointLight::new(position, intensity)),
+      LightDescriptor::Directional { direction, irradiance } => Box::new(DirectionalLight::new(direction, irradiance)),
+      LightDescriptor::Spot { position, direction, intensity, cutoff, falloff_start } => {
+        Box::new(SpotLight::new(position, direction, intensity, cutoff, falloff_start))
+      },
+      LightDescriptor::Sphere { center, radius, radiance } => Box::new(SphereLight::new(center, radius, radiance)),
+    }
+  }
+}
+
+impl Serialize for Box<dyn Light + Send> {
+  fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+    self.describe().serialize(serializer)
+  }
+}
+
+impl<'de> Deserialize<'de> for Box<dyn Light + Send> {
+  fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+    Ok(LightDescriptor::deserialize(deserializer)?.build())
+  }
+}
+
+pub trait LightClone {
+  fn box_clone(&self) -> Box<dyn Light + Send>;
+}
+
+impl<T> LightClone for T where T: 'static + Light + Clone + Send {
+  fn box_clone(&self) -> Box<dyn Light + Send> {
+    Box::new(self.clone())
+  }
+}
+
+impl Clone for Box<dyn Light + Send> {
+  fn clone(&self) -> Self {
+    self.box_clone()
+  }
+}
+
+#[derive(Clone)]
+pub struct PointLight {
+  pub position: Vector3,
+  pub intensity: Spectrum,
+}
+
+impl PointLight {
+  pub fn new(position: Vector3, intensity: Spectrum) -> Self {
+    Self { position, intensity }
+  }
+}
+
+impl Light for PointLight {
+  fn sample_li(&self, position: Vector3, _u: (f32, f32)) -> Option<LightSample> {
+    let diff = self.position - position;
+    let distance = diff.mag();
+    if distance == 0.0 {
+      return None;
+    }
+    Some(LightSample {
+      wi: diff / distance,
+      distance,
+      radiance: self.intensity / (distance * distance),
+      pdf: 1.0,
+    })
+  }
+
+  fn describe(&self) -> LightDescriptor {
+    LightDescriptor::Point { position: self.position, intensity: self.intensity }
+  }
+}
+
+/// Light arriving from infinitely far away along `direction`
+#[derive(Clone)]
+pub struct DirectionalLight {
+  pub direction: Vector3,
+  pub irradiance: Spectrum,
+}
+
+impl DirectionalLight {
+  pub fn new(direction: Vector3, irradiance: Spectrum) -> Self {
+    Self { direction: direction.normalize(), irradiance }
+  }
+}
+
+impl Light for DirectionalLight {
+  fn sample_li(&self, _position: Vector3, _u: (f32, f32)) -> Option<LightSample> {
+    Some(LightSample {
+      wi: -self.direction,
+      distance: f32::INFINITY,
+      radiance: self.irradiance,
+      pdf: 1.0,
+    })
+  }
+
+  fn describe(&self) -> LightDescriptor {
+    LightDescriptor::Directional { direction: self.direction, irradiance: self.irradiance }
+  }
+}
+
+/// Point light restricted to a cone. Full intensity inside `falloff_start`,
+/// smoothly fading to zero at `cutoff` (both half angles in radians).
+#[derive(Clone)]
+pub struct SpotLight {
+  pub position: Vector3,
+  pub direction: Vector3,
+  pub intensity: Spectrum,
+  pub cutoff: f32,
+  pub falloff_start: f32,
+}
+
+impl SpotLight {
+  pub fn new(position: Vector3, direction: Vector3, intensity: Spectrum, cutoff: f32, falloff_start: f32) -> Self {
+    Self {
+      position,
+      direction: direction.normalize(),
+      intensity,
+      cutoff,
+      falloff_start: falloff_start.min(cutoff),
+    }
+  }
+
+  fn falloff(&self, w: Vector3) -> f32 {
+    let cos_theta = w.dot(&self.direction);
+    let cos_cutoff = self.cutoff.cos();
+    let cos_start = self.falloff_start.cos();
+    if cos_theta < cos_cutoff {
+      0.0
+    } else if cos_theta >= cos_start {
+      1.0
+    } else {
+      let delta = (cos_theta - cos_cutoff) / (cos_start - cos_cutoff);
+      delta * delta * (3.0 - 2.0 * delta)
+    }
+  }
+}
+
+impl Light for SpotLight {
+  fn sample_li(&self, position: Vector3, _u: (f32, f32)) -> Option<LightSample> {
+    let diff = self.position - position;
+    let distance = diff.mag();
+    if distance == 0.0 {
+      return None;
+    }
+    let wi = diff / distance;
+    let falloff = self.falloff(-wi);
+    if falloff == 0.0 {
+      return None;
+    }
+    Some(LightSample {
+      wi,
+      distance,
+      radiance: self.intensity * (falloff / (distance * distance)),
+      pdf: 1.0,
+    })
+  }
+
+  fn describe(&self) -> LightDescriptor {
+    LightDescriptor::Spot {
+      position: self.position,
+      direction: self.direction,
+      intensity: self.intensity,
+      cutoff: self.cutoff,
+      falloff_start: self.falloff_start,
+    }
+  }
+}
+
+/// Spherical area light uniformly emitting `radiance` from its surface
+#[derive(Clone)]
+pub struct SphereLight {
+  pub center: Vector3,
+  pub radius: f32,
+  pub radiance: Spectrum,
+}
+
+impl SphereLight {
+  pub fn new(center: Vector3, radius: f32, radiance: Spectrum) -> Self {
+    Self { center, radius, radiance }
+  }
+
+  /// Cosine of the half angle of the cone subtended by the sphere, or
+  /// `None` when `position` lies inside of it
+  fn cos_theta_max(&self, position: Vector3) -> Option<f32> {
+    let dist2 = (self.center - position).mag2();
+    let r2 = self.radius * self.radius;
+    if dist2 <= r2 {
+      None
+    } else {
+      Some((1.0 - r2 / dist2).max(0.0).sqrt())
+    }
+  }
+}
+
+impl Light for SphereLight {
+  fn sample_li(&self, position: Vector3, u: (f32, f32)) -> Option<LightSample> {
+    let cos_theta_max = self.cos_theta_max(position)?;
+
+    // Uniformly sample the cone of directions subtended by the sphere
+    let axis = (self.center - position).normalize();
+    let cos_theta = 1.0 - u.0 * (1.0 - cos_theta_max);
+    let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+    let phi = 2.0 * PI * u.1;
+    let wi = vec3!(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta).from_local(&axis);
+
+    // Distance to the visible side of the sphere along `wi`
+    let distance = match self.intersect(&Ray::new(position, wi)) {
+      Some((t, _)) => t,
+      None => (self.center - position).dot(&wi),
+    };
+    Some(LightSample {
+      wi,
+      distance,
+      radiance: self.radiance,
+      pdf: 1.0 / (2.0 * PI * (1.0 - cos_theta_max)),
+    })
+  }
+
+  fn pdf_li(&self, position: Vector3, wi: Vector3) -> f32 {
+    match self.cos_theta_max(position) {
+      Some(cos_theta_max) => {
+        let axis = (self.center - position).normalize();
+        if wi.dot(&axis) >= cos_theta_max {
+          1.0 / (2.0 * PI * (1.0 - cos_theta_max))
+        } else {
+          0.0
+        }
+      },
+      None => 0.0
+    }
+  }
+
+  fn intersect(&self, ray: &Ray) -> Option<(f32, Spectrum)> {
+    let oc = ray.origin - self.center;
+    let b = oc.dot(&ray.direction);
+    let c = oc.mag2() - self.radius * self.radius;
+    let d = b * b - c;
+    if d < 0.0 {
+      return None;
+    }
+    let t = -b - d.sqrt();
+    if t > 0.0 {
+      Some((t, self.radiance))
+    } else {
+      None
+    }
+  }
+
+  fn is_delta(&self) -> bool {
+    false
+  }
+
+  fn describe(&self) -> LightDescriptor {
+    LightDescriptor::Sphere { center: self.center, radius: self.radius, radiance: self.radiance }
+  }
+}
@@ -0,0 +1,360 @@
+use std::f32::consts::PI;
+
+use serde::{Serialize, Serializer, Deserialize, Deserializer};
+
+use ::math::{Vector3, Spectrum, cosine_sample_hemisphere};
+
+/// Result of importance sampling a material
+pub struct BsdfSample {
+  /// Sampled incident direction, pointing away from the surface
+  pub wi: Vector3,
+
+  /// BSDF value times the cosine term, divided by the pdf
+  pub weight: Spectrum,
+
+  /// Solid angle density of `wi`. For specular lobes this is the discrete
+  /// probability of having picked the lobe.
+  pub pdf: f32,
+
+  pub is_specular: bool,
+}
+
+/// All directions point away from the surface. `normal` is the outward
+/// geometric normal, so `wo` may lie on either side of it.
+pub trait Material: MaterialClone + Sync {
+
+  /// Rough overall color of the surface, used for previews
+  fn albedo(&self) -> Spectrum;
+
+  /// BSDF value for light arriving from `wi` and leaving towards `wo`.
+  /// Always zero for perfectly specular materials.
+  fn eval(&self, wo: Vector3, wi: Vector3, normal: Vector3) -> Spectrum;
+
+  /// Solid angle density with which `sample` would produce `wi`
+  fn pdf(&self, wo: Vector3, wi: Vector3, normal: Vector3) -> f32;
+
+  fn sample(&self, wo: Vector3, normal: Vector3, u: (f32, f32)) -> Option<BsdfSample>;
+
+  /// Whether the material only scatters into discrete directions
+  fn is_specular(&self) -> bool {
+    false
+  }
+
+  /// Every discrete direction light leaving towards `wo` comes from, along
+  /// with the fraction of it that is carried over. Lets deterministic
+  /// integrators follow all specular lobes instead of sampling one.
+  fn specular_directions(&self, _wo: Vector3, _normal: Vector3) -> Vec<(Vector3, Spectrum)> {
+    vec![]
+  }
+
+  /// Parameters the material can be rebuilt from
+  fn describe(&self) -> MaterialDescriptor;
+}
+
+/// Serializable form of the materials, the same as the material
+/// descriptors of the JS bindings, e.g. `{ "type": "glass", "ior": 1.5, "tint": [1, 1, 1] }`
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+pub enum MaterialDescriptor {
+  Diffuse { albedo: Spectrum },
+  Mirror { reflectance: Spectrum },
+  Glossy { diffuse: Spectrum, specular: Spectrum, exponent: f32 },
+  Glass { ior: f32, tint: Spectrum },
+}
+
+impl MaterialDescriptor {
+  pub fn build(self) -> Box<dyn Material + Send> {
+    match self {
+      MaterialDescriptor::Diffuse { albedo } => Box::new(Lambertian::new(albedo)),
+      MaterialDescriptor::Mirror { reflectance } => Box::new(Mirror::new(reflectance)),
+      MaterialDescriptor::Glossy { diffuse, specular, exponent } => Box::new(Glossy::new(diffuse, specular, exponent)),
+      MaterialDescriptor::Glass { ior, tint } => Box::new(Dielectric::new(ior, tint)),
+    }
+  }
+}
+
+impl Serialize for Box<dyn Material + Send> {
+  fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+    self.describe().serialize(serializer)
+  }
+}
+
+impl<'de> Deserialize<'de> for Box<dyn Material + Send> {
+  fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+    Ok(MaterialDescriptor::deserialize(deserializer)?.build())
+  }
+}
+
+pub trait MaterialClone {
+  fn box_clone(&self) -> Box<dyn Material + Send>;
+}
+
+impl<T> MaterialClone for T where T: 'static + Material + Clone + Send {
+  fn box_clone(&self) -> Box<dyn Material + Send> {
+    Box::new(self.clone())
+  }
+}
+
+impl Clone for Box<dyn Material + Send> {
+  fn clone(&self) -> Self {
+    self.box_clone()
+  }
+}
+
+/// Flip the normal to the side of `wo`; used by two-sided materials
+fn facing(wo: Vector3, normal: Vector3) -> Vector3 {
+  if wo.dot(&normal) < 0.0 { -normal } else { normal }
+}
+
+fn same_hemisphere(wo: Vector3, wi: Vector3, normal: Vector3) -> bool {
+  wo.dot(&normal) * wi.dot(&normal) > 0.0
+}
+
+#[derive(Clone)]
+pub struct Lambertian {
+  pub albedo: Spectrum,
+}
+
+impl Lambertian {
+  pub fn new(albedo: Spectrum) -> Self {
+    Self { albedo }
+  }
+}
+
+impl Material for Lambertian {
+  fn albedo(&self) -> Spectrum {
+    self.albedo
+  }
+
+  fn eval(&self, wo: Vector3, wi: Vector3, normal: Vector3) -> Spectrum {
+    if same_hemisphere(wo, wi, normal) { self.albedo / PI } else { Vector3::zero() }
+  }
+
+  fn pdf(&self, wo: Vector3, wi: Vector3, normal: Vector3) -> f32 {
+    if same_hemisphere(wo, wi, normal) { wi.dot(&normal).abs() / PI } else { 0.0 }
+  }
+
+  fn sample(&self, wo: Vector3, normal: Vector3, u: (f32, f32)) -> Option<BsdfSample> {
+    let n = facing(wo, normal);
+    let wi = cosine_sample_hemisphere(u).from_local(&n);
+    let pdf = wi.dot(&n) / PI;
+    if pdf <= 0.0 {
+      return None;
+    }
+    Some(BsdfSample { wi, weight: self.albedo, pdf, is_specular: false })
+  }
+
+  fn describe(&self) -> MaterialDescriptor {
+    MaterialDescriptor::Diffuse { albedo: self.albedo }
+  }
+}
+
+#[derive(Clone)]
+pub struct Mirror {
+  pub reflectance: Spectrum,
+}
+
+impl Mirror {
+  pub fn new(reflectance: Spectrum) -> Self {
+    Self { reflectance }
+  }
+}
+
+impl Material for Mirror {
+  fn albedo(&self) -> Spectrum {
+    self.reflectance
+  }
+
+  fn eval(&self, _wo: Vector3, _wi: Vector3, _normal: Vector3) -> Spectrum {
+    Vector3::zero()
+  }
+
+  fn pdf(&self, _wo: Vector3, _wi: Vector3, _normal: Vector3) -> f32 {
+    0.0
+  }
+
+  fn sample(&self, wo: Vector3, normal: Vector3, _u: (f32, f32)) -> Option<BsdfSample> {
+    let wi = wo.reflect(&facing(wo, normal));
+    Some(BsdfSample { wi, weight: self.reflectance, pdf: 1.0, is_specular: true })
+  }
+
+  fn is_specular(&self) -> bool {
+    true
+  }
+
+  fn specular_directions(&self, wo: Vector3, normal: Vector3) -> Vec<(Vector3, Spectrum)> {
+    vec![(wo.reflect(&facing(wo, normal)), self.reflectance)]
+  }
+
+  fn describe(&self) -> MaterialDescriptor {
+    MaterialDescriptor::Mirror { reflectance: self.reflectance }
+  }
+}
+
+/// Energy normalized Phong: a Lambertian base plus a specular lobe around
+/// the mirror direction
+#[derive(Clone)]
+pub struct Glossy {
+  pub diffuse: Spectrum,
+  pub specular: Spectrum,
+  pub exponent: f32,
+}
+
+impl Glossy {
+  pub fn new(diffuse: Spectrum, specular: Spectrum, exponent: f32) -> Self {
+    Self { diffuse, specular, exponent }
+  }
+
+  /// Probability of sampling the specular lobe rather than the diffuse one
+  fn specular_probability(&self) -> f32 {
+    let d = self.diffuse.max_component();
+    let s = self.specular.max_component();
+    if d + s > 0.0 { s / (d + s) } else { 0.0 }
+  }
+}
+
+impl Material for Glossy {
+  fn albedo(&self) -> Spectrum {
+    self.diffuse + self.specular
+  }
+
+  fn eval(&self, wo: Vector3, wi: Vector3, normal: Vector3) -> Spectrum {
+    if !same_hemisphere(wo, wi, normal) {
+      return Vector3::zero();
+    }
+    let r = wo.reflect(&facing(wo, normal));
+    let cos_alpha = r.dot(&wi).max(0.0);
+    let norm = (self.exponent + 2.0) / (2.0 * PI);
+    self.diffuse / PI + self.specular * (norm * cos_alpha.powf(self.exponent))
+  }
+
+  fn pdf(&self, wo: Vector3, wi: Vector3, normal: Vector3) -> f32 {
+    if !same_hemisphere(wo, wi, normal) {
+      return 0.0;
+    }
+    let ps = self.specular_probability();
+    let r = wo.reflect(&facing(wo, normal));
+    let cos_alpha = r.dot(&wi).max(0.0);
+    let diffuse_pdf = wi.dot(&normal).abs() / PI;
+    let specular_pdf = (self.exponent + 1.0) / (2.0 * PI) * cos_alpha.powf(self.exponent);
+    (1.0 - ps) * diffuse_pdf + ps * specular_pdf
+  }
+
+  fn sample(&self, wo: Vector3, normal: Vector3, u: (f32, f32)) -> Option<BsdfSample> {
+    let n = facing(wo, normal);
+    let ps = self.specular_probability();
+    let wi = if u.0 < ps {
+      // Reuse the first dimension after choosing the lobe
+      let u0 = u.0 / ps;
+      let cos_alpha = u0.powf(1.0 / (self.exponent + 1.0));
+      let sin_alpha = (1.0 - cos_alpha * cos_alpha).max(0.0).sqrt();
+      let phi = 2.0 * PI * u.1;
+      let local = vec3!(sin_alpha * phi.cos(), sin_alpha * phi.sin(), cos_alpha);
+      local.from_local(&wo.reflect(&n))
+    } else {
+      let u0 = (u.0 - ps) / (1.0 - ps);
+      cosine_sample_hemisphere((u0, u.1)).from_local(&n)
+    };
+    let cos_theta = wi.dot(&n);
+    if cos_theta <= 0.0 {
+      return None;
+    }
+    let pdf = self.pdf(wo, wi, normal);
+    if pdf <= 0.0 {
+      return None;
+    }
+    let weight = self.eval(wo, wi, normal) * (cos_theta / pdf);
+    Some(BsdfSample { wi, weight, pdf, is_specular: false })
+  }
+
+  fn describe(&self) -> MaterialDescriptor {
+    MaterialDescriptor::Glossy { diffuse: self.diffuse, specular: self.specular, exponent: self.exponent }
+  }
+}
+
+/// Smooth glass interface with index of refraction `ior` on the inside
+#[derive(Clone)]
+pub struct Dielectric {
+  pub ior: f32,
+  pub tint: Spectrum,
+}
+
+impl Dielectric {
+  pub fn new(ior: f32, tint: Spectrum) -> Self {
+    Self { ior, tint }
+  }
+
+  /// Fresnel reflectance, mirror direction and, unless there is total
+  /// internal reflection, the refracted direction with its radiance scale
+  fn scatter(&self, wo: Vector3, normal: Vector3) -> (f32, Vector3, Option<(Vector3, f32)>) {
+    let entering = wo.dot(&normal) > 0.0;
+    let n = if entering { normal } else { -normal };
+    let eta = if entering { 1.0 / self.ior } else { self.ior };
+    let cos_i = wo.dot(&n);
+    let fresnel = fresnel_dielectric(cos_i, eta);
+    let reflected = wo.reflect(&n);
+    if fresnel >= 1.0 {
+      return (fresnel, reflected, None);
+    }
+    let sin2_t = eta * eta * (1.0 - cos_i * cos_i);
+    let cos_t = (1.0 - sin2_t).sqrt();
+    let refracted = (-wo * eta + n * (eta * cos_i - cos_t)).normalize();
+
+    // Radiance is compressed or spread out by the change of medium
+    (fresnel, reflected, Some((refracted, eta * eta)))
+  }
+}
+
+/// Unpolarized Fresnel reflectance of a dielectric interface. `cos_i` is
+/// measured on the incident side and `eta` is `eta_i / eta_t`.
+pub fn fresnel_dielectric(cos_i: f32, eta: f32) -> f32 {
+  let sin2_t = eta * eta * (1.0 - cos_i * cos_i).max(0.0);
+  if sin2_t >= 1.0 {
+    return 1.0;
+  }
+  let cos_t = (1.0 - sin2_t).sqrt();
+  let r_parl = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+  let r_perp = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+  (r_parl * r_parl + r_perp * r_perp) / 2.0
+}
+
+impl Material for Dielectric {
+  fn albedo(&self) -> Spectrum {
+    self.tint
+  }
+
+  fn eval(&self, _wo: Vector3, _wi: Vector3, _normal: Vector3) -> Spectrum {
+    Vector3::zero()
+  }
+
+  fn pdf(&self, _wo: Vector3, _wi: Vector3, _normal: Vector3) -> f32 {
+    0.0
+  }
+
+  fn sample(&self, wo: Vector3, normal: Vector3, u: (f32, f32)) -> Option<BsdfSample> {
+    let (fresnel, reflected, refracted) = self.scatter(wo, normal);
+    match refracted {
+      Some((wi, scale)) if u.0 >= fresnel => {
+        Some(BsdfSample { wi, weight: self.tint * scale, pdf: 1.0 - fresnel, is_specular: true })
+      },
+      _ => Some(BsdfSample { wi: reflected, weight: self.tint, pdf: fresnel, is_specular: true })
+    }
+  }
+
+  fn is_specular(&self) -> bool {
+    true
+  }
+
+  fn specular_directions(&self, wo: Vector3, normal: Vector3) -> Vec<(Vector3, Spectrum)> {
+    let (fresnel, reflected, refracted) = self.scatter(wo, normal);
+    let mut directions = vec![(reflected, self.tint * fresnel)];
+    if let Some((wi, scale)) = refracted {
+      directions.push((wi, self.tint * ((1.0 - fresnel) * scale)));
+    }
+    directions
+  }
+
+  fn describe(&self) -> MaterialDescriptor {
+    MaterialDescriptor::Glass { ior: self.ior, tint: self.tint }
+  }
+}
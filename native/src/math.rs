@@ -1,4 +1,5 @@
 use std::ops::{Add, Sub, Mul, Div, Neg, Index, IndexMut};
+use serde::{Serialize, Serializer, Deserialize, Deserializer};
 
 #[derive(Debug, Clone)]
 pub struct Color {
@@ -9,7 +10,7 @@ pub struct Color {
 }
 
 fn f32_to_u8(num: f32) -> u8 {
-  let num = if num > 1.0 { 1.0 } else if num < 0.0 { 0.0 } else { num };
+  let num = num.clamp(0.0, 1.0);
   (num * 255.0) as u8
 }
 
@@ -56,6 +57,7 @@ pub struct Vector3 {
   pub z: f32,
 }
 
+#[macro_export]
 macro_rules! vec3 {
   ($c:expr) => (Vector3::new($c, $c, $c));
   ($x:expr, $y:expr, $z:expr) => (Vector3::new($x, $y, $z));
@@ -123,6 +125,30 @@ impl Vector3 {
     Vector3 { x: self.x / mag, y: self.y / mag, z: self.z / mag }
   }
 
+  pub fn max_component(&self) -> f32 {
+    self.x.max(self.y).max(self.z)
+  }
+
+  /// Mirror `self` about `normal`, both pointing away from the surface
+  pub fn reflect(&self, normal: &Vector3) -> Vector3 {
+    *normal * (2.0 * self.dot(normal)) - *self
+  }
+
+  /// Two unit vectors that together with `self` (assumed normalized) form
+  /// an orthonormal basis
+  pub fn orthonormal_basis(&self) -> (Vector3, Vector3) {
+    let up = if self.x.abs() > 0.9 { Vector3::j() } else { Vector3::i() };
+    let t = self.cross(up).normalize();
+    let b = self.cross(t);
+    (t, b)
+  }
+
+  /// Interpret `self` as coordinates in the frame whose z axis is `normal`
+  pub fn from_local(&self, normal: &Vector3) -> Vector3 {
+    let (t, b) = normal.orthonormal_basis();
+    t * self.x + b * self.y + *normal * self.z
+  }
+
   pub fn transform(self, mat: Matrix4) -> Vector3 {
     let vec4 = Vector4::vec3w(self, 0.0);
     let transf = mat * vec4;
@@ -210,6 +236,19 @@ impl Mul<f32> for Vector3 {
   }
 }
 
+/// Component-wise product, mostly used to attenuate a `Spectrum`
+impl Mul<Vector3> for Vector3 {
+  type Output = Self;
+
+  fn mul(self, rhs: Self) -> Self {
+    Self {
+      x: self.x * rhs.x,
+      y: self.y * rhs.y,
+      z: self.z * rhs.z,
+    }
+  }
+}
+
 impl Div<f32> for Vector3 {
   type Output = Self;
 
@@ -234,6 +273,68 @@ impl Div<Vector3> for Vector3 {
   }
 }
 
+pub type Spectrum = Vector3;
+
+/// Cosine weighted direction on the hemisphere around +z, from a uniform
+/// sample on the unit square. Pdf is `cos(theta) / PI`.
+pub fn cosine_sample_hemisphere(u: (f32, f32)) -> Vector3 {
+  let r = u.0.sqrt();
+  let phi = 2.0 * std::f32::consts::PI * u.1;
+  vec3!(r * phi.cos(), r * phi.sin(), (1.0 - u.0).max(0.0).sqrt())
+}
+
+/// Uniform point on the unit disk using Shirley's concentric mapping, which
+/// keeps strata of `u` compact
+pub fn concentric_sample_disk(u: (f32, f32)) -> (f32, f32) {
+  let (x, y) = (2.0 * u.0 - 1.0, 2.0 * u.1 - 1.0);
+  if x == 0.0 && y == 0.0 {
+    return (0.0, 0.0);
+  }
+  let quarter_pi = std::f32::consts::PI / 4.0;
+  let (r, theta) = if x.abs() > y.abs() {
+    (x, quarter_pi * (y / x))
+  } else {
+    (y, 2.0 * quarter_pi - quarter_pi * (x / y))
+  };
+  (r * theta.cos(), r * theta.sin())
+}
+
+/// Uniform point on the regular polygon with `sides` corners inscribed in
+/// the unit circle, the first corner lying at angle `rotation`
+pub fn sample_regular_polygon(u: (f32, f32), sides: u32, rotation: f32) -> (f32, f32) {
+  // Pick one of the triangles fanning out from the center, reusing the
+  // remainder of the first dimension
+  let sides_f = sides as f32;
+  let scaled = u.0 * sides_f;
+  let side = scaled.floor().min(sides_f - 1.0);
+  let u0 = scaled - side;
+
+  // Uniform point in the triangle (center, corner a, corner b)
+  let step = 2.0 * std::f32::consts::PI / sides_f;
+  let angle_a = rotation + side * step;
+  let angle_b = angle_a + step;
+  let su = u0.sqrt();
+  let (wa, wb) = (su * (1.0 - u.1), su * u.1);
+  (
+    wa * angle_a.cos() + wb * angle_b.cos(),
+    wa * angle_a.sin() + wb * angle_b.sin(),
+  )
+}
+
+/// Vectors are written as `[x, y, z]` arrays
+impl Serialize for Vector3 {
+  fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+    [self.x, self.y, self.z].serialize(serializer)
+  }
+}
+
+impl<'de> Deserialize<'de> for Vector3 {
+  fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+    let [x, y, z] = <[f32; 3]>::deserialize(deserializer)?;
+    Ok(Vector3 { x, y, z })
+  }
+}
+
 #[derive(Debug, Clone, Copy)]
 pub struct Vector4 {
   pub x: f32,
@@ -311,22 +412,36 @@ impl IndexMut<u8> for Vector4 {
   }
 }
 
+/// Written as `[x, y, z, w]` arrays, like quaternions
+impl Serialize for Vector4 {
+  fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+    [self.x, self.y, self.z, self.w].serialize(serializer)
+  }
+}
+
+impl<'de> Deserialize<'de> for Vector4 {
+  fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+    let [x, y, z, w] = <[f32; 4]>::deserialize(deserializer)?;
+    Ok(Vector4 { x, y, z, w })
+  }
+}
+
 pub type Quaternion = Vector4;
 
-impl Into<Matrix4> for Quaternion {
-  fn into(self) -> Matrix4 {
+impl From<Quaternion> for Matrix4 {
+  fn from(q: Quaternion) -> Matrix4 {
     Matrix4 {
-      a11: 1.0 - 2.0 * self.y * self.y - 2.0 * self.z * self.z,
-      a12: 2.0 * self.x * self.y - 2.0 * self.z * self.w,
-      a13: 2.0 * self.x * self.z + 2.0 * self.y * self.w,
+      a11: 1.0 - 2.0 * q.y * q.y - 2.0 * q.z * q.z,
+      a12: 2.0 * q.x * q.y - 2.0 * q.z * q.w,
+      a13: 2.0 * q.x * q.z + 2.0 * q.y * q.w,
       a14: 0.0,
-      a21: 2.0 * self.x * self.y + 2.0 * self.z * self.w,
-      a22: 1.0 - 2.0 * self.x * self.x - 2.0 * self.z * self.z,
-      a23: 2.0 * self.y * self.z - 2.0 * self.x * self.w,
+      a21: 2.0 * q.x * q.y + 2.0 * q.z * q.w,
+      a22: 1.0 - 2.0 * q.x * q.x - 2.0 * q.z * q.z,
+      a23: 2.0 * q.y * q.z - 2.0 * q.x * q.w,
       a24: 0.0,
-      a31: 2.0 * self.x * self.z - 2.0 * self.y * self.w,
-      a32: 2.0 * self.y * self.z + 2.0 * self.x * self.w,
-      a33: 1.0 - 2.0 * self.x * self.x - 2.0 * self.y * self.y,
+      a31: 2.0 * q.x * q.z - 2.0 * q.y * q.w,
+      a32: 2.0 * q.y * q.z + 2.0 * q.x * q.w,
+      a33: 1.0 - 2.0 * q.x * q.x - 2.0 * q.y * q.y,
       a34: 0.0,
       a41: 0.0,
       a42: 0.0,
@@ -463,7 +578,8 @@ impl Matrix4 {
     }
   }
 
-  pub fn inverse(self) -> Self {
+  /// `None` for singular matrices, e.g. ones scaling an axis to zero
+  pub fn inverse(self) -> Option<Self> {
 
     // Cache the value
     let a00 = self.a11;
@@ -499,8 +615,8 @@ impl Matrix4 {
 
     // Calculate the determinant
     let det = b00 * b11 - b01 * b10 + b02 * b09 + b03 * b08 - b04 * b07 + b05 * b06;
-    if det == 0.0 {
-      panic!("Matrix not invertible");
+    if det == 0.0 || !det.is_finite() {
+      return None;
     }
     let det = 1.0 / det;
 
@@ -522,12 +638,12 @@ impl Matrix4 {
     let o43 = (a31 * b01 - a30 * b03 - a32 * b00) * det;
     let o44 = (a20 * b03 - a21 * b01 + a22 * b00) * det;
 
-    Self {
+    Some(Self {
       a11: o11, a12: o12, a13: o13, a14: o14,
       a21: o21, a22: o22, a23: o23, a24: o24,
       a31: o31, a32: o32, a33: o33, a34: o34,
       a41: o41, a42: o42, a43: o43, a44: o44,
-    }
+    })
   }
 
   pub fn scale_matrix(scale: Vector3) -> Self {
@@ -0,0 +1,133 @@
+use std::sync::Arc;
+
+use ::math::Vector3;
+use ::util::{Ray, Intersection};
+use ::intersectable::{Intersectable, ShapeDescriptor};
+use ::bounded::{Bounded, BoundingBox};
+use ::bvh::Bvh;
+
+/// Indexed triangle mesh. Normals and UVs, when present, are given per
+/// vertex and share the vertex indices. The buffers are reference counted so
+/// that cloning a scene does not copy them.
+#[derive(Clone)]
+pub struct TriangleMesh {
+  pub positions: Arc<Vec<Vector3>>,
+
+  /// Either empty, for flat shading, or one normal per vertex
+  pub normals: Arc<Vec<Vector3>>,
+
+  /// Either empty or one texture coordinate per vertex
+  pub uvs: Arc<Vec<(f32, f32)>>,
+
+  /// Counter clockwise vertex indices of every triangle
+  pub triangles: Arc<Vec<[u32; 3]>>,
+
+  bvh: Arc<Bvh>,
+}
+
+impl TriangleMesh {
+  /// Build a mesh and its BVH. Indices must be in range and `normals` and
+  /// `uvs` must either be empty or as long as `positions`.
+  pub fn new(positions: Vec<Vector3>, normals: Vec<Vector3>, uvs: Vec<(f32, f32)>, triangles: Vec<[u32; 3]>) -> Self {
+    let bvh = Bvh::new(triangles.iter().enumerate().map(|(index, tri)| {
+      let bb = BoundingBox::new(positions[tri[0] as usize], positions[tri[0] as usize]);
+      (index, bb.extend(positions[tri[1] as usize]).extend(positions[tri[2] as usize]))
+    }).collect());
+    Self {
+      positions: Arc::new(positions),
+      normals: Arc::new(normals),
+      uvs: Arc::new(uvs),
+      triangles: Arc::new(triangles),
+      bvh: Arc::new(bvh),
+    }
+  }
+
+  /// Build a mesh from flat `[x, y, z, ...]` positions and normals, `[u, v,
+  /// ...]` UVs and triangle indices, checking that they are consistent
+  pub fn from_flat(positions: &[f32], normals: &[f32], uvs: &[f32], indices: &[u32]) -> Result<Self, String> {
+    let num_vertices = positions.len() / 3;
+    if !positions.len().is_multiple_of(3) || !indices.len().is_multiple_of(3) {
+      return Err(String::from("mesh positions and indices must come in triples"));
+    }
+    if !normals.is_empty() && normals.len() != positions.len() {
+      return Err(String::from("mesh normals must match the number of vertices"));
+    }
+    if !uvs.is_empty() && uvs.len() != num_vertices * 2 {
+      return Err(String::from("mesh uvs must match the number of vertices"));
+    }
+    if let Some(index) = indices.iter().find(|&&i| i as usize >= num_vertices) {
+      return Err(format!("mesh index {} is out of range", index));
+    }
+
+    let positions = positions.chunks(3).map(|p| vec3!(p[0], p[1], p[2])).collect();
+    let normals = normals.chunks(3).map(|n| vec3!(n[0], n[1], n[2])).collect();
+    let uvs = uvs.chunks(2).map(|uv| (uv[0], uv[1])).collect();
+    let triangles = indices.chunks(3).map(|t| [t[0], t[1], t[2]]).collect();
+    Ok(Self::new(positions, normals, uvs, triangles))
+  }
+
+  /// Möller-Trumbore ray triangle intersection
+  fn intersect_triangle(&self, ray: &Ray, index: usize) -> Option<Intersection<'_>> {
+    let [i0, i1, i2] = self.triangles[index];
+    let (i0, i1, i2) = (i0 as usize, i1 as usize, i2 as usize);
+    let p0 = self.positions[i0];
+    let e1 = self.positions[i1] - p0;
+    let e2 = self.positions[i2] - p0;
+    let pvec = ray.direction.cross(e2);
+    let det = e1.dot(&pvec);
+    if det == 0.0 {
+      return None;
+    }
+    let inv_det = 1.0 / det;
+    let tvec = ray.origin - p0;
+    let u = tvec.dot(&pvec) * inv_det;
+    if !(0.0..=1.0).contains(&u) {
+      return None;
+    }
+    let qvec = tvec.cross(e1);
+    let v = ray.direction.dot(&qvec) * inv_det;
+    if v < 0.0 || u + v > 1.0 {
+      return None;
+    }
+    let t = e2.dot(&qvec) * inv_det;
+    if t <= 0.0 {
+      return None;
+    }
+
+    // Barycentric interpolation of the vertex attributes
+    let w = 1.0 - u - v;
+    let normal = if self.normals.is_empty() {
+      e1.cross(e2).normalize()
+    } else {
+      (self.normals[i0] * w + self.normals[i1] * u + self.normals[i2] * v).normalize()
+    };
+    let uv = if self.uvs.is_empty() {
+      None
+    } else {
+      let (uv0, uv1, uv2) = (self.uvs[i0], self.uvs[i1], self.uvs[i2]);
+      Some((uv0.0 * w + uv1.0 * u + uv2.0 * v, uv0.1 * w + uv1.1 * u + uv2.1 * v))
+    };
+    Some(Intersection { position: ray.point_at(t), normal, t, uv, material: None, object: None })
+  }
+}
+
+impl Intersectable for TriangleMesh {
+  fn intersect(&self, ray: &Ray) -> Option<Intersection<'_>> {
+    self.bvh.intersect(ray, |index| self.intersect_triangle(ray, index))
+  }
+
+  fn describe(&self) -> ShapeDescriptor {
+    ShapeDescriptor::Mesh {
+      positions: self.positions.iter().flat_map(|p| vec![p.x, p.y, p.z]).collect(),
+      normals: self.normals.iter().flat_map(|n| vec![n.x, n.y, n.z]).collect(),
+      uvs: self.uvs.iter().flat_map(|&(u, v)| vec![u, v]).collect(),
+      indices: self.triangles.iter().flat_map(|t| t.to_vec()).collect(),
+    }
+  }
+}
+
+impl Bounded for TriangleMesh {
+  fn bounding_box(&self) -> BoundingBox {
+    self.bvh.bounding_box()
+  }
+}
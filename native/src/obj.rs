@@ -0,0 +1,403 @@
+use std::collections::HashMap;
+use std::error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use ::math::{Vector3, Spectrum};
+use ::mesh::TriangleMesh;
+use ::material::{Material, Lambertian, Mirror, Glossy, Dielectric};
+
+#[derive(Debug)]
+pub enum ObjError {
+  Io(PathBuf, io::Error),
+
+  /// Malformed statement in the given file and (1 based) line
+  Parse(PathBuf, usize, String),
+}
+
+impl fmt::Display for ObjError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      ObjError::Io(path, err) => write!(f, "{}: {}", path.display(), err),
+      ObjError::Parse(path, line, message) => write!(f, "{}:{}: {}", path.display(), line, message),
+    }
+  }
+}
+
+impl error::Error for ObjError {}
+
+/// Material parameters of an MTL `newmtl` block
+#[derive(Clone, Debug)]
+pub struct MtlMaterial {
+  pub name: String,
+  pub diffuse: Spectrum,
+  pub specular: Spectrum,
+  pub shininess: f32,
+  pub ior: f32,
+  pub dissolve: f32,
+  pub transmission: Spectrum,
+  pub illum: u32,
+}
+
+impl MtlMaterial {
+  fn new(name: &str) -> Self {
+    Self {
+      name: name.to_string(),
+      diffuse: vec3!(0.8),
+      specular: Vector3::zero(),
+      shininess: 0.0,
+      ior: 1.5,
+      dissolve: 1.0,
+      transmission: vec3!(1.0),
+      illum: 2,
+    }
+  }
+
+  /// Closest of our material models. Refractive illumination models and
+  /// translucent materials become glass, reflective ones without a diffuse
+  /// part become mirrors.
+  pub fn to_material(&self) -> Box<dyn Material + Send> {
+    let refractive = matches!(self.illum, 4 | 6 | 7 | 9);
+    let reflective = matches!(self.illum, 3 | 5);
+    if refractive || self.dissolve < 1.0 {
+      Box::new(Dielectric::new(self.ior, self.transmission))
+    } else if reflective && self.diffuse.max_component() <= 0.0 {
+      Box::new(Mirror::new(self.specular))
+    } else if self.specular.max_component() > 0.0 {
+      Box::new(Glossy::new(self.diffuse, self.specular, self.shininess.max(1.0)))
+    } else {
+      Box::new(Lambertian::new(self.diffuse))
+    }
+  }
+}
+
+/// Faces of one `o`/`g` group sharing a single material
+pub struct ObjPart {
+  pub name: String,
+  pub mesh: TriangleMesh,
+  pub material: Box<dyn Material + Send>,
+}
+
+/// Parts of an OBJ file, along with the problems worked around while
+/// loading it, each prefixed with its file and line
+pub struct ObjModel {
+  pub parts: Vec<ObjPart>,
+  pub warnings: Vec<String>,
+}
+
+/// Triangles of a part before they get turned into a mesh. OBJ indexes
+/// positions, UVs and normals separately, so every distinct combination
+/// becomes a vertex of its own.
+struct PartBuilder {
+  name: String,
+  material: Option<String>,
+  vertices: HashMap<(usize, Option<usize>, Option<usize>), u32>,
+  keys: Vec<(usize, Option<usize>, Option<usize>)>,
+  triangles: Vec<[u32; 3]>,
+}
+
+impl PartBuilder {
+  fn new(name: String, material: Option<String>) -> Self {
+    Self { name, material, vertices: HashMap::new(), keys: vec![], triangles: vec![] }
+  }
+
+  fn vertex(&mut self, key: (usize, Option<usize>, Option<usize>)) -> u32 {
+    let keys = &mut self.keys;
+    *self.vertices.entry(key).or_insert_with(|| {
+      keys.push(key);
+      (keys.len() - 1) as u32
+    })
+  }
+
+  /// Normals and UVs are only kept if every vertex of the part has one
+  fn build(self, positions: &[Vector3], uvs: &[(f32, f32)], normals: &[Vector3]) -> TriangleMesh {
+    let mesh_positions = self.keys.iter().map(|&(v, _, _)| positions[v]).collect();
+    let mesh_uvs = if self.keys.iter().all(|&(_, vt, _)| vt.is_some()) {
+      self.keys.iter().filter_map(|&(_, vt, _)| vt.map(|vt| uvs[vt])).collect()
+    } else {
+      vec![]
+    };
+    let mesh_normals = if self.keys.iter().all(|&(_, _, vn)| vn.is_some()) {
+      self.keys.iter().filter_map(|&(_, _, vn)| vn.map(|vn| normals[vn])).collect()
+    } else {
+      vec![]
+    };
+    TriangleMesh::new(mesh_positions, mesh_normals, mesh_uvs, self.triangles)
+  }
+}
+
+fn read_file(path: &Path) -> Result<String, ObjError> {
+  fs::read_to_string(path).map_err(|err| ObjError::Io(path.to_path_buf(), err))
+}
+
+fn parse_floats(args: &[&str], count: usize, path: &Path, line: usize) -> Result<Vec<f32>, ObjError> {
+  if args.len() < count {
+    return Err(ObjError::Parse(path.to_path_buf(), line, format!("expected {} numbers, found {}", count, args.len())));
+  }
+  args.iter().take(count).map(|arg| arg.parse::<f32>().map_err(|_| {
+    ObjError::Parse(path.to_path_buf(), line, format!("invalid number \"{}\"", arg))
+  })).collect()
+}
+
+/// Resolve a 1 based, possibly negative (relative to the end) OBJ index
+fn resolve_index(token: &str, len: usize, path: &Path, line: usize) -> Result<usize, ObjError> {
+  let invalid = || ObjError::Parse(path.to_path_buf(), line, format!("invalid index \"{}\"", token));
+  let index = token.parse::<i64>().map_err(|_| invalid())?;
+  let resolved = if index < 0 { len as i64 + index } else { index - 1 };
+  if resolved < 0 || resolved >= len as i64 {
+    return Err(ObjError::Parse(path.to_path_buf(), line, format!("index {} is out of range", index)));
+  }
+  Ok(resolved as usize)
+}
+
+pub fn load_mtl(path: &Path) -> Result<Vec<MtlMaterial>, ObjError> {
+  let source = read_file(path)?;
+  let mut materials: Vec<MtlMaterial> = vec![];
+  for (number, line) in source.lines().enumerate() {
+    let number = number + 1;
+    let tokens: Vec<&str> = line.split('#').next().unwrap_or("").split_whitespace().collect();
+    let (keyword, args) = match tokens.split_first() {
+      Some((keyword, args)) => (*keyword, args),
+      None => continue
+    };
+    if keyword == "newmtl" {
+      let name = args.join(" ");
+      materials.push(MtlMaterial::new(&name));
+      continue;
+    }
+    let material = match materials.last_mut() {
+      Some(material) => material,
+      None => return Err(ObjError::Parse(path.to_path_buf(), number, format!("\"{}\" before any newmtl", keyword)))
+    };
+    match keyword {
+      "Kd" => { let c = parse_floats(args, 3, path, number)?; material.diffuse = vec3!(c[0], c[1], c[2]); },
+      "Ks" => { let c = parse_floats(args, 3, path, number)?; material.specular = vec3!(c[0], c[1], c[2]); },
+      "Tf" => { let c = parse_floats(args, 3, path, number)?; material.transmission = vec3!(c[0], c[1], c[2]); },
+      "Ns" => material.shininess = parse_floats(args, 1, path, number)?[0],
+      "Ni" => material.ior = parse_floats(args, 1, path, number)?[0],
+      "d" => material.dissolve = parse_floats(args, 1, path, number)?[0],
+      "Tr" => material.dissolve = 1.0 - parse_floats(args, 1, path, number)?[0],
+      "illum" => material.illum = parse_floats(args, 1, path, number)?[0] as u32,
+
+      // Ambient and emissive colors as well as texture maps are not supported
+      _ => {}
+    }
+  }
+  Ok(materials)
+}
+
+/// Load an OBJ file along with the MTL libraries it references, relative to
+/// its directory. Every group/material combination becomes one part. Faces
+/// using a material none of the libraries define get the default one.
+pub fn load_obj(path: &Path) -> Result<ObjModel, ObjError> {
+  let source = read_file(path)?;
+  let directory = path.parent().unwrap_or(Path::new("."));
+
+  let mut positions: Vec<Vector3> = vec![];
+  let mut uvs: Vec<(f32, f32)> = vec![];
+  let mut normals: Vec<Vector3> = vec![];
+  let mut materials: HashMap<String, MtlMaterial> = HashMap::new();
+  let mut parts: Vec<PartBuilder> = vec![];
+  let mut warnings = vec![];
+  let mut current = PartBuilder::new(String::from("default"), None);
+
+  for (number, line) in source.lines().enumerate() {
+    let number = number + 1;
+    let tokens: Vec<&str> = line.split('#').next().unwrap_or("").split_whitespace().collect();
+    let (keyword, args) = match tokens.split_first() {
+      Some((keyword, args)) => (*keyword, args),
+      None => continue
+    };
+    match keyword {
+      "v" => {
+        let p = parse_floats(args, 3, path, number)?;
+        positions.push(vec3!(p[0], p[1], p[2]));
+      },
+      "vt" => {
+        let t = parse_floats(args, 1, path, number)?;
+        let v = if args.len() > 1 { parse_floats(&args[1..], 1, path, number)?[0] } else { 0.0 };
+        uvs.push((t[0], v));
+      },
+      "vn" => {
+        let n = parse_floats(args, 3, path, number)?;
+        normals.push(vec3!(n[0], n[1], n[2]));
+      },
+      "f" => {
+        if args.len() < 3 {
+          return Err(ObjError::Parse(path.to_path_buf(), number, String::from("face with less than 3 vertices")));
+        }
+        let mut face = Vec::with_capacity(args.len());
+        for arg in args {
+          let mut indices = arg.split('/');
+          let v = resolve_index(indices.next().unwrap_or(""), positions.len(), path, number)?;
+          let vt = match indices.next() {
+            Some(token) if !token.is_empty() => Some(resolve_index(token, uvs.len(), path, number)?),
+            _ => None
+          };
+          let vn = match indices.next() {
+            Some(token) if !token.is_empty() => Some(resolve_index(token, normals.len(), path, number)?),
+            _ => None
+          };
+          face.push(current.vertex((v, vt, vn)));
+        }
+
+        // Fan triangulation, assuming convex polygons
+        for i in 1..face.len() - 1 {
+          current.triangles.push([face[0], face[i], face[i + 1]]);
+        }
+      },
+      "o" | "g" => {
+        let name = if args.is_empty() { String::from("default") } else { args.join(" ") };
+        let material = current.material.clone();
+        parts.push(current);
+        current = PartBuilder::new(name, material);
+      },
+      "usemtl" => {
+        let name = args.join(" ");
+        let material = if materials.contains_key(&name) {
+          Some(name)
+        } else {
+          warnings.push(format!("{}:{}: unknown material \"{}\", using the default one", path.display(), number, name));
+          None
+        };
+        let part_name = current.name.clone();
+        parts.push(current);
+        current = PartBuilder::new(part_name, material);
+      },
+      "mtllib" => {
+        for file in args {
+          for material in load_mtl(&directory.join(file))? {
+            materials.insert(material.name.clone(), material);
+          }
+        }
+      },
+
+      // Smoothing groups, lines, points and free-form geometry are ignored
+      _ => {}
+    }
+  }
+  parts.push(current);
+
+  let parts = parts.into_iter().filter(|part| !part.triangles.is_empty()).map(|part| {
+    let material = match part.material {
+      Some(ref name) => materials[name].to_material(),
+      None => Box::new(Lambertian::new(vec3!(0.8))) as Box<dyn Material + Send>
+    };
+    let name = part.name.clone();
+    ObjPart { name, mesh: part.build(&positions, &uvs, &normals), material }
+  }).collect();
+  Ok(ObjModel { parts, warnings })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::env;
+  use std::process;
+
+  /// Write `files` as `(name, contents)` into a fresh directory and load the
+  /// first one
+  fn load(test: &str, files: &[(&str, &str)]) -> Result<ObjModel, ObjError> {
+    let directory = env::temp_dir().join(format!("photon-obj-{}-{}", test, process::id()));
+    fs::create_dir_all(&directory).unwrap();
+    for &(name, contents) in files {
+      fs::write(directory.join(name), contents).unwrap();
+    }
+    let result = load_obj(&directory.join(files[0].0));
+    fs::remove_dir_all(&directory).unwrap();
+    result
+  }
+
+  fn xyz(v: Vector3) -> (f32, f32, f32) {
+    (v.x, v.y, v.z)
+  }
+
+  /// Positions of the corners of every triangle of `mesh`
+  fn corners(mesh: &TriangleMesh) -> Vec<[(f32, f32, f32); 3]> {
+    mesh.triangles.iter().map(|tri| {
+      [xyz(mesh.positions[tri[0] as usize]), xyz(mesh.positions[tri[1] as usize]), xyz(mesh.positions[tri[2] as usize])]
+    }).collect()
+  }
+
+  #[test]
+  fn faces_get_fan_triangulated() {
+    let model = load("faces", &[("quad.obj", "
+      v 0 0 0
+      v 1 0 0
+      v 1 1 0
+      v 0 1 0
+      vt 0 0
+      vt 1 0
+      vt 1 1
+      vt 0 1
+      vn 0 0 1
+      f 1/1/1 2/2/1 3/3/1 4/4/1
+    ")]).unwrap();
+    assert_eq!(model.parts.len(), 1);
+    let mesh = &model.parts[0].mesh;
+    assert_eq!(corners(mesh), vec![
+      [(0.0, 0.0, 0.0), (1.0, 0.0, 0.0), (1.0, 1.0, 0.0)],
+      [(0.0, 0.0, 0.0), (1.0, 1.0, 0.0), (0.0, 1.0, 0.0)],
+    ]);
+    assert_eq!(mesh.positions.len(), 4);
+    assert_eq!(mesh.uvs.len(), 4);
+    assert_eq!(mesh.normals.len(), 4);
+  }
+
+  #[test]
+  fn negative_indices_count_from_the_end() {
+    let model = load("negative", &[("tri.obj", "
+      v 0 0 0
+      v 5 5 5
+      v 1 0 0
+      v 0 1 0
+      f -3 -2 -1
+    ")]).unwrap();
+    assert_eq!(corners(&model.parts[0].mesh), vec![
+      [(5.0, 5.0, 5.0), (1.0, 0.0, 0.0), (0.0, 1.0, 0.0)],
+    ]);
+  }
+
+  #[test]
+  fn out_of_range_indices_fail() {
+    match load("range", &[("tri.obj", "v 0 0 0\nv 1 0 0\nf 1 2 3\n")]) {
+      Err(ObjError::Parse(_, line, _)) => assert_eq!(line, 3),
+      _ => panic!("expected a parse error"),
+    }
+  }
+
+  #[test]
+  fn usemtl_splits_parts_by_material() {
+    let model = load("usemtl", &[
+      ("scene.obj", "
+        mtllib scene.mtl
+        v 0 0 0
+        v 1 0 0
+        v 0 1 0
+        o shape
+        usemtl red
+        f 1 2 3
+        usemtl blue
+        f 1 2 3
+        usemtl missing
+        f 1 2 3
+      "),
+      ("scene.mtl", "
+        newmtl red
+        Kd 1 0 0
+        newmtl blue
+        Kd 0 0 1
+      "),
+    ]).unwrap();
+    let names: Vec<&str> = model.parts.iter().map(|part| part.name.as_str()).collect();
+    assert_eq!(names, vec!["shape", "shape", "shape"]);
+    let albedos: Vec<_> = model.parts.iter().map(|part| xyz(part.material.albedo())).collect();
+    assert_eq!(albedos, vec![(1.0, 0.0, 0.0), (0.0, 0.0, 1.0), (0.8, 0.8, 0.8)]);
+
+    // The unknown material falls back to the default one with a warning
+    assert_eq!(model.warnings.len(), 1);
+    assert!(model.warnings[0].ends_with(":11: unknown material \"missing\", using the default one"));
+  }
+}
@@ -1,17 +1,77 @@
+use ::math::Matrix4;
 use ::intersectable::Intersectable;
-use ::util::{Transform, Ray, Intersection};
+use ::material::Material;
+use ::util::{Transform, Ray, Intersection, Placement};
+use ::bounded::{Bounded, BoundingBox};
 
-#[derive(Clone)]
+/// Scene files load objects through `scene::ObjectContents`, so that
+/// instances can find their prototype
+#[derive(Clone, Serialize)]
 pub struct Object {
+  /// Identifies the object within its scene, assigned by `Scene::add_object`
+  #[serde(skip)]
+  pub id: usize,
+
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub name: Option<String>,
+
+  /// Id of the group the object hangs from, `None` at the root of the
+  /// scene. Scene files nest objects into their groups instead.
+  #[serde(skip)]
+  pub parent: Option<usize>,
+
+  /// Placement relative to the parent group
   pub transform: Transform,
   pub intersectable: Box<dyn Intersectable + Send>,
+  pub material: Box<dyn Material + Send>,
+}
+
+/// Node of the scene graph carrying nothing but a transform, so that
+/// everything below it moves as a unit
+#[derive(Clone, Serialize)]
+pub struct Group {
+  /// Identifies the group within its scene, shared with the object ids
+  pub id: usize,
+  pub name: Option<String>,
+
+  /// Id of the enclosing group, `None` at the root of the scene
+  pub parent: Option<usize>,
+  pub transform: Transform,
 }
 
 impl Object {
-  pub fn intersect(&self, ray: &Ray) -> Option<Intersection> {
-    let transf = self.transform.into();
-    let transf_ray = ray.inverse_transform(transf);
+  /// Intersect the object as placed by `world`, its own transform composed
+  /// with those of every group above it
+  pub fn intersect(&self, ray: &Ray, world: &Placement) -> Option<Intersection<'_>> {
+    let transf_ray = ray.inverse_transform(world);
     let maybe_itsct = self.intersectable.intersect(&transf_ray);
-    maybe_itsct.map(|itsct| itsct.transform(transf))
+    maybe_itsct.map(|itsct| {
+      let itsct = itsct.transform(world);
+
+      // `t` is measured in object space, recompute it so that intersections
+      // with differently scaled objects can be compared
+      let t = (itsct.position - ray.origin).mag();
+      // Parts of instanced prototypes may bring their own material
+      let material = itsct.material.or(Some(&*self.material));
+      Intersection { t, material, ..itsct }
+    })
+  }
+
+  /// World space bounds of the object as placed by `world`. Unbounded
+  /// shapes stay infinite.
+  pub fn world_bounding_box(&self, world: Matrix4) -> BoundingBox {
+    let bb = self.intersectable.bounding_box();
+    if bb.is_finite() {
+      bb.transform(world)
+    } else {
+      bb
+    }
   }
-}
\ No newline at end of file
+}
+impl Bounded for Object {
+  /// Bounds in the space of the parent group, or the world for objects at
+  /// the root
+  fn bounding_box(&self) -> BoundingBox {
+    self.world_bounding_box(self.transform.into())
+  }
+}
@@ -0,0 +1,487 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use ::math::Spectrum;
+use ::film::Film;
+use ::tonemap::ToneMapper;
+use ::aov::AovBuffer;
+
+/// File formats a film can be saved as. PNG and PPM hold the 8 bit image as
+/// displayed, PFM and OpenEXR the linear radiance.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ImageFormat {
+  Png,
+  Ppm,
+  Pfm,
+  ExrHalf,
+  ExrFloat,
+}
+
+impl ImageFormat {
+  /// Parse `"png"`, `"ppm"`, `"pfm"`, `"exr"` (half floats) or `"exr32"`
+  pub fn from_name(name: &str) -> Option<Self> {
+    match name.to_lowercase().as_str() {
+      "png" => Some(ImageFormat::Png),
+      "ppm" => Some(ImageFormat::Ppm),
+      "pfm" => Some(ImageFormat::Pfm),
+      "exr" => Some(ImageFormat::ExrHalf),
+      "exr32" => Some(ImageFormat::ExrFloat),
+      _ => None,
+    }
+  }
+
+  /// Guess the format from the file extension. EXR files get half floats.
+  pub fn from_path(path: &Path) -> Option<Self> {
+    path.extension().and_then(|ext| ext.to_str()).and_then(Self::from_name)
+  }
+}
+
+/// Precision of the channels of an OpenEXR file
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ExrPrecision {
+  Half,
+  Float,
+}
+
+/// Save the film to `path`, overwriting any existing file. The 8 bit
+/// formats get tone mapped with `tone_mapper`, the others keep the radiance.
+/// The AOVs become layers of EXR files and are left out of the others.
+/// Empty films fail, none of the formats can hold them.
+pub fn save_film(film: &Film, aovs: &[AovBuffer], tone_mapper: &ToneMapper, path: &Path, format: ImageFormat) -> io::Result<()> {
+  if film.width == 0 || film.height == 0 {
+    let message = format!("cannot save an empty {}x{} image", film.width, film.height);
+    return Err(io::Error::new(io::ErrorKind::InvalidInput, message));
+  }
+  let mut writer = BufWriter::new(File::create(path)?);
+  let (width, height) = (film.width, film.height);
+  match format {
+    ImageFormat::Png => write_png(&mut writer, width, height, &rgba_pixels(film, tone_mapper))?,
+    ImageFormat::Ppm => write_ppm(&mut writer, width, height, &rgba_pixels(film, tone_mapper))?,
+    ImageFormat::Pfm => write_pfm(&mut writer, width, height, &radiance_pixels(film))?,
+    ImageFormat::ExrHalf | ImageFormat::ExrFloat => {
+      let precision = if format == ImageFormat::ExrHalf { ExrPrecision::Half } else { ExrPrecision::Float };
+      let pixels = radiance_pixels(film);
+      let r: Vec<f32> = pixels.iter().map(|p| p.x).collect();
+      let g: Vec<f32> = pixels.iter().map(|p| p.y).collect();
+      let b: Vec<f32> = pixels.iter().map(|p| p.z).collect();
+      let mut layers = vec![];
+      for buffer in aovs {
+        for (index, channel) in buffer.aov.channels().iter().enumerate() {
+          layers.push((format!("{}.{}", buffer.aov.name(), channel), buffer.channel(index)));
+        }
+      }
+      let mut channels: Vec<(&str, &[f32])> = vec![("R", &r), ("G", &g), ("B", &b)];
+      channels.extend(layers.iter().map(|(name, values)| (name.as_str(), values.as_slice())));
+      write_exr(&mut writer, width, height, &channels, precision)?;
+    },
+  }
+  writer.flush()
+}
+
+fn radiance_pixels(film: &Film) -> Vec<Spectrum> {
+  let mut pixels = Vec::with_capacity(film.width * film.height);
+  for y in 0..film.height {
+    for x in 0..film.width {
+      pixels.push(film.pixel(x, y));
+    }
+  }
+  pixels
+}
+
+fn rgba_pixels(film: &Film, tone_mapper: &ToneMapper) -> Vec<u8> {
+  let mut pixels = Vec::with_capacity(film.width * film.height * 4);
+  for y in 0..film.height {
+    for x in 0..film.width {
+      let color = tone_mapper.map(film.pixel(x, y), x, y);
+      pixels.extend_from_slice(&[color.r, color.g, color.b, color.a]);
+    }
+  }
+  pixels
+}
+
+/// Binary PPM of the RGB channels of top to bottom RGBA rows
+pub fn write_ppm<W: Write>(writer: &mut W, width: usize, height: usize, rgba: &[u8]) -> io::Result<()> {
+  write!(writer, "P6\n{} {}\n255\n", width, height)?;
+  for pixel in rgba.chunks(4) {
+    writer.write_all(&pixel[..3])?;
+  }
+  Ok(())
+}
+
+/// Little endian color PFM. The format stores its rows bottom to top.
+pub fn write_pfm<W: Write>(writer: &mut W, width: usize, height: usize, pixels: &[Spectrum]) -> io::Result<()> {
+  write!(writer, "PF\n{} {}\n-1.0\n", width, height)?;
+  for row in pixels.chunks(width).rev() {
+    for pixel in row {
+      for value in &[pixel.x, pixel.y, pixel.z] {
+        writer.write_all(&value.to_le_bytes())?;
+      }
+    }
+  }
+  Ok(())
+}
+
+fn crc32_table() -> [u32; 256] {
+  let mut table = [0; 256];
+  for (n, entry) in table.iter_mut().enumerate() {
+    let mut c = n as u32;
+    for _ in 0..8 {
+      c = if c & 1 != 0 { 0xedb8_8320 ^ (c >> 1) } else { c >> 1 };
+    }
+    *entry = c;
+  }
+  table
+}
+
+fn write_png_chunk<W: Write>(writer: &mut W, table: &[u32; 256], kind: &[u8; 4], data: &[u8]) -> io::Result<()> {
+  writer.write_all(&(data.len() as u32).to_be_bytes())?;
+  writer.write_all(kind)?;
+  writer.write_all(data)?;
+  let crc = kind.iter().chain(data).fold(0xffff_ffffu32, |c, &byte| {
+    table[((c ^ byte as u32) & 0xff) as usize] ^ (c >> 8)
+  });
+  writer.write_all(&(crc ^ 0xffff_ffff).to_be_bytes())
+}
+
+/// 8 bit RGBA PNG of top to bottom rows. The image data is stored in
+/// uncompressed deflate blocks, trading file size for simplicity.
+pub fn write_png<W: Write>(writer: &mut W, width: usize, height: usize, rgba: &[u8]) -> io::Result<()> {
+  // Every row starts with its filter type, none here
+  let mut raw = Vec::with_capacity((width * 4 + 1) * height);
+  for row in rgba.chunks(width * 4) {
+    raw.push(0);
+    raw.extend_from_slice(row);
+  }
+
+  // Zlib stream made of stored blocks of at most 65535 bytes
+  let mut zlib = vec![0x78, 0x01];
+  let mut blocks = raw.chunks(0xffff).peekable();
+  if blocks.peek().is_none() {
+    zlib.extend_from_slice(&[1, 0, 0, 0xff, 0xff]);
+  }
+  while let Some(block) = blocks.next() {
+    let last = blocks.peek().is_none();
+    let len = block.len() as u16;
+    zlib.push(last as u8);
+    zlib.extend_from_slice(&len.to_le_bytes());
+    zlib.extend_from_slice(&(!len).to_le_bytes());
+    zlib.extend_from_slice(block);
+  }
+  let (a, b) = raw.iter().fold((1u32, 0u32), |(a, b), &byte| {
+    let a = (a + byte as u32) % 65521;
+    (a, (b + a) % 65521)
+  });
+  zlib.extend_from_slice(&((b << 16) | a).to_be_bytes());
+
+  let table = crc32_table();
+  let mut header = Vec::with_capacity(13);
+  header.extend_from_slice(&(width as u32).to_be_bytes());
+  header.extend_from_slice(&(height as u32).to_be_bytes());
+  // Bit depth 8, RGBA, deflate, adaptive filtering, no interlacing
+  header.extend_from_slice(&[8, 6, 0, 0, 0]);
+
+  writer.write_all(b"\x89PNG\r\n\x1a\n")?;
+  write_png_chunk(writer, &table, b"IHDR", &header)?;
+  write_png_chunk(writer, &table, b"IDAT", &zlib)?;
+  write_png_chunk(writer, &table, b"IEND", &[])
+}
+
+/// IEEE 754 half precision bits of `value`, rounding to nearest even.
+/// Values out of range become infinities.
+pub fn f32_to_f16(value: f32) -> u16 {
+  let bits = value.to_bits();
+  let sign = ((bits >> 16) & 0x8000) as u16;
+  let exponent = ((bits >> 23) & 0xff) as i32;
+  let mantissa = bits & 0x007f_ffff;
+
+  if exponent == 0xff {
+    // Infinity, or NaN keeping some payload bit set
+    return sign | 0x7c00 | if mantissa != 0 { 0x0200 } else { 0 };
+  }
+  let exponent = exponent - 127 + 15;
+  if exponent >= 0x1f {
+    return sign | 0x7c00;
+  }
+  if exponent <= 0 {
+    // Subnormal half, or zero
+    if exponent < -10 {
+      return sign;
+    }
+    let mantissa = mantissa | 0x0080_0000;
+    let shift = (14 - exponent) as u32;
+    let half = mantissa >> shift;
+    let rest = mantissa & ((1 << shift) - 1);
+    let halfway = 1 << (shift - 1);
+    let round = rest > halfway || (rest == halfway && half & 1 != 0);
+    return sign | (half + round as u32) as u16;
+  }
+  let half = ((exponent as u32) << 10) | (mantissa >> 13);
+  let rest = mantissa & 0x1fff;
+  let round = rest > 0x1000 || (rest == 0x1000 && half & 1 != 0);
+
+  // A carry out of the mantissa correctly bumps the exponent
+  sign | (half + round as u32) as u16
+}
+
+fn write_exr_attribute<W: Write>(writer: &mut W, name: &str, kind: &str, value: &[u8]) -> io::Result<()> {
+  writer.write_all(name.as_bytes())?;
+  writer.write_all(&[0])?;
+  writer.write_all(kind.as_bytes())?;
+  writer.write_all(&[0])?;
+  writer.write_all(&(value.len() as i32).to_le_bytes())?;
+  writer.write_all(value)
+}
+
+/// Uncompressed scanline OpenEXR with one channel per `(name, values)`
+/// pair, each holding `width * height` values of top to bottom rows. Dotted
+/// names such as `"normal.X"` put channels into layers.
+pub fn write_exr<W: Write>(
+  writer: &mut W,
+  width: usize,
+  height: usize,
+  channels: &[(&str, &[f32])],
+  precision: ExrPrecision
+) -> io::Result<()> {
+  // Channels must be listed, and stored, in alphabetical order
+  let mut channels = channels.to_vec();
+  channels.sort_by(|a, b| a.0.cmp(b.0));
+  let (pixel_type, sample_size) = match precision {
+    ExrPrecision::Half => (1i32, 2),
+    ExrPrecision::Float => (2i32, 4),
+  };
+
+  let mut channel_list = vec![];
+  for &(name, _) in &channels {
+    channel_list.extend_from_slice(name.as_bytes());
+    channel_list.push(0);
+    channel_list.extend_from_slice(&pixel_type.to_le_bytes());
+    // Not perceptually linear, three reserved bytes, no subsampling
+    channel_list.extend_from_slice(&[0, 0, 0, 0]);
+    channel_list.extend_from_slice(&1i32.to_le_bytes());
+    channel_list.extend_from_slice(&1i32.to_le_bytes());
+  }
+  channel_list.push(0);
+
+  let mut window = vec![];
+  for value in &[0, 0, width as i32 - 1, height as i32 - 1] {
+    window.extend_from_slice(&value.to_le_bytes());
+  }
+
+  let mut header = vec![];
+  header.extend_from_slice(&[0x76, 0x2f, 0x31, 0x01]);
+  header.extend_from_slice(&2u32.to_le_bytes());
+  write_exr_attribute(&mut header, "channels", "chlist", &channel_list)?;
+  write_exr_attribute(&mut header, "compression", "compression", &[0])?;
+  write_exr_attribute(&mut header, "dataWindow", "box2i", &window)?;
+  write_exr_attribute(&mut header, "displayWindow", "box2i", &window)?;
+  write_exr_attribute(&mut header, "lineOrder", "lineOrder", &[0])?;
+  write_exr_attribute(&mut header, "pixelAspectRatio", "float", &1f32.to_le_bytes())?;
+  write_exr_attribute(&mut header, "screenWindowCenter", "v2f", &[0; 8])?;
+  write_exr_attribute(&mut header, "screenWindowWidth", "float", &1f32.to_le_bytes())?;
+  header.push(0);
+  writer.write_all(&header)?;
+
+  // Offset table pointing at every scanline block, which holds the row
+  // number, the data size and then the row of each channel in turn
+  let line_size = width * channels.len() * sample_size;
+  let first_line = header.len() + height * 8;
+  for y in 0..height {
+    let offset = (first_line + y * (line_size + 8)) as u64;
+    writer.write_all(&offset.to_le_bytes())?;
+  }
+  let mut line = Vec::with_capacity(line_size);
+  for y in 0..height {
+    line.clear();
+    for &(_, values) in &channels {
+      for &value in &values[y * width..(y + 1) * width] {
+        match precision {
+          ExrPrecision::Half => line.extend_from_slice(&f32_to_f16(value).to_le_bytes()),
+          ExrPrecision::Float => line.extend_from_slice(&value.to_le_bytes()),
+        }
+      }
+    }
+    writer.write_all(&(y as i32).to_le_bytes())?;
+    writer.write_all(&(line_size as i32).to_le_bytes())?;
+    writer.write_all(&line)?;
+  }
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::convert::TryInto;
+  use std::sync::Arc;
+  use ::math::Vector3;
+  use ::filter::BoxFilter;
+
+  const RGBA: [u8; 16] = [255, 0, 0, 255, 0, 255, 0, 128, 0, 0, 255, 0, 10, 20, 30, 40];
+
+  fn radiance() -> Vec<Spectrum> {
+    vec![vec3!(0.0, 0.5, 1.0), vec3!(2.0, 0.25, 0.125), vec3!(-1.0, 3.0, 0.75), vec3!(65504.0, 1e-3, 0.0)]
+  }
+
+  fn u32_be(bytes: &[u8]) -> u32 {
+    u32::from_be_bytes(bytes[..4].try_into().unwrap())
+  }
+
+  fn u32_le(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes(bytes[..4].try_into().unwrap())
+  }
+
+  fn f16_to_f32(bits: u16) -> f32 {
+    let sign = if bits & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exponent = ((bits >> 10) & 0x1f) as i32;
+    let mantissa = (bits & 0x3ff) as f32;
+    match exponent {
+      0 => sign * mantissa * 2f32.powi(-24),
+      0x1f => if mantissa == 0.0 { sign * f32::INFINITY } else { f32::NAN },
+      _ => sign * (1.0 + mantissa / 1024.0) * 2f32.powi(exponent - 15),
+    }
+  }
+
+  #[test]
+  fn png_holds_the_rgba_rows() {
+    let mut png = vec![];
+    write_png(&mut png, 2, 2, &RGBA).unwrap();
+    assert_eq!(&png[..8], b"\x89PNG\r\n\x1a\n");
+
+    // Walk the chunks, checking their CRC
+    let table = crc32_table();
+    let (mut pos, mut chunks) = (8, vec![]);
+    while pos < png.len() {
+      let len = u32_be(&png[pos..]) as usize;
+      let (kind, data) = (&png[pos + 4..pos + 8], &png[pos + 8..pos + 8 + len]);
+      let crc = kind.iter().chain(data).fold(0xffff_ffffu32, |c, &byte| table[((c ^ byte as u32) & 0xff) as usize] ^ (c >> 8));
+      assert_eq!(crc ^ 0xffff_ffff, u32_be(&png[pos + 8 + len..]));
+      chunks.push((kind.to_vec(), data.to_vec()));
+      pos += len + 12;
+    }
+    let kinds: Vec<&[u8]> = chunks.iter().map(|(kind, _)| kind.as_slice()).collect();
+    assert_eq!(kinds, vec![&b"IHDR"[..], b"IDAT", b"IEND"]);
+    assert_eq!(chunks[0].1, vec![0, 0, 0, 2, 0, 0, 0, 2, 8, 6, 0, 0, 0]);
+
+    // A single stored block of filter type 0 rows
+    let zlib = &chunks[1].1;
+    assert_eq!(&zlib[..3], &[0x78, 0x01, 1]);
+    let len = u16::from_le_bytes([zlib[3], zlib[4]]);
+    assert_eq!(!len, u16::from_le_bytes([zlib[5], zlib[6]]));
+    let raw = &zlib[7..7 + len as usize];
+    let mut expected = vec![0];
+    expected.extend_from_slice(&RGBA[..8]);
+    expected.push(0);
+    expected.extend_from_slice(&RGBA[8..]);
+    assert_eq!(raw, expected.as_slice());
+    assert_eq!(zlib.len(), 7 + len as usize + 4);
+  }
+
+  #[test]
+  fn pfm_holds_the_rows_bottom_to_top() {
+    let pixels = radiance();
+    let mut pfm = vec![];
+    write_pfm(&mut pfm, 2, 2, &pixels).unwrap();
+    let header = b"PF\n2 2\n-1.0\n";
+    assert_eq!(&pfm[..header.len()], header);
+    let values: Vec<f32> = pfm[header.len()..].chunks(4).map(|bytes| f32::from_bits(u32_le(bytes))).collect();
+    let expected: Vec<f32> = pixels[2..].iter().chain(&pixels[..2]).flat_map(|p| vec![p.x, p.y, p.z]).collect();
+    assert_eq!(values, expected);
+  }
+
+  /// Channels of a 2x2 EXR written by `write_exr`, by name
+  fn read_exr(exr: &[u8], sample_size: usize) -> Vec<(String, Vec<u16>, Vec<f32>)> {
+    assert_eq!(&exr[..8], &[0x76, 0x2f, 0x31, 0x01, 2, 0, 0, 0]);
+
+    // Attributes up to the empty name closing the header
+    let mut pos = 8;
+    let mut names = vec![];
+    while exr[pos] != 0 {
+      let name_end = pos + exr[pos..].iter().position(|&b| b == 0).unwrap();
+      let kind_end = name_end + 1 + exr[name_end + 1..].iter().position(|&b| b == 0).unwrap();
+      let size = u32_le(&exr[kind_end + 1..]) as usize;
+      let value = &exr[kind_end + 5..kind_end + 5 + size];
+      if &exr[pos..name_end] == b"channels" {
+        let mut entry = value;
+        while entry[0] != 0 {
+          let end = entry.iter().position(|&b| b == 0).unwrap();
+          names.push(String::from_utf8(entry[..end].to_vec()).unwrap());
+          entry = &entry[end + 17..];
+        }
+      }
+      if &exr[pos..name_end] == b"dataWindow" {
+        let window: Vec<u32> = value.chunks(4).map(u32_le).collect();
+        assert_eq!(window, vec![0, 0, 1, 1]);
+      }
+      pos = kind_end + 5 + size;
+    }
+    pos += 1;
+
+    let mut channels: Vec<(String, Vec<u16>, Vec<f32>)> = names.into_iter().map(|name| (name, vec![], vec![])).collect();
+    for y in 0..2 {
+      let offset = u32_le(&exr[pos + y * 8..]) as usize;
+      assert_eq!(u32_le(&exr[offset..]), y as u32);
+      let line_size = u32_le(&exr[offset + 4..]) as usize;
+      assert_eq!(line_size, channels.len() * 2 * sample_size);
+      let line = &exr[offset + 8..offset + 8 + line_size];
+      for (index, channel) in channels.iter_mut().enumerate() {
+        for sample in line[index * 2 * sample_size..(index + 1) * 2 * sample_size].chunks(sample_size) {
+          if sample_size == 2 {
+            channel.1.push(u16::from_le_bytes([sample[0], sample[1]]));
+          } else {
+            channel.2.push(f32::from_bits(u32_le(sample)));
+          }
+        }
+      }
+    }
+    channels
+  }
+
+  #[test]
+  fn exr_holds_sorted_channels() {
+    let pixels = radiance();
+    let r: Vec<f32> = pixels.iter().map(|p| p.x).collect();
+    let g: Vec<f32> = pixels.iter().map(|p| p.y).collect();
+    let depth = [1.0, 2.0, f32::INFINITY, 0.5];
+    let channels: Vec<(&str, &[f32])> = vec![("R", &r), ("G", &g), ("depth.Z", &depth)];
+
+    let mut exr = vec![];
+    write_exr(&mut exr, 2, 2, &channels, ExrPrecision::Float).unwrap();
+    let read = read_exr(&exr, 4);
+    let names: Vec<&str> = read.iter().map(|(name, _, _)| name.as_str()).collect();
+    assert_eq!(names, vec!["G", "R", "depth.Z"]);
+    assert_eq!(read[0].2, g);
+    assert_eq!(read[1].2, r);
+    assert_eq!(read[2].2, depth.to_vec());
+
+    let mut exr = vec![];
+    write_exr(&mut exr, 2, 2, &channels, ExrPrecision::Half).unwrap();
+    let read = read_exr(&exr, 2);
+    for ((_, half, _), values) in read.iter().zip(&[&g[..], &r[..], &depth[..]]) {
+      let decoded: Vec<f32> = half.iter().map(|&bits| f16_to_f32(bits)).collect();
+      for (&actual, &expected) in decoded.iter().zip(values.iter()) {
+        // Every test value but 1e-3 is exact in half precision
+        assert!(actual == expected || (actual - expected).abs() < 1e-6, "{} for {}", actual, expected);
+      }
+    }
+  }
+
+  #[test]
+  fn half_conversion_rounds_to_nearest() {
+    assert_eq!(f32_to_f16(1.0), 0x3c00);
+    assert_eq!(f32_to_f16(-2.0), 0xc000);
+    assert_eq!(f32_to_f16(65504.0), 0x7bff);
+    assert_eq!(f32_to_f16(1e6), 0x7c00);
+    assert_eq!(f32_to_f16(2f32.powi(-24)), 0x0001);
+    assert_eq!(f32_to_f16(1.0 + 2f32.powi(-11)), 0x3c00);
+    assert_eq!(f32_to_f16(1.0 + 3.0 * 2f32.powi(-11)), 0x3c02);
+  }
+
+  #[test]
+  fn empty_films_are_not_saved() {
+    let film = Film::new(0, 4, Arc::new(BoxFilter { radius: 0.5 }));
+    let path = ::std::env::temp_dir().join(format!("photon-empty-{}.png", ::std::process::id()));
+    let err = save_film(&film, &[], &ToneMapper::new(), &path, ImageFormat::Png).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    assert!(!path.exists());
+  }
+}
@@ -1,17 +1,25 @@
 
+use std::ops::Range;
+use std::path::PathBuf;
 use std::sync::mpsc::{self, RecvTimeoutError, TryRecvError};
 use std::sync::{Arc, Mutex};
-use std::time::Duration;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
 use std::thread;
 use neon::prelude::*;
 
-use math::{Color, Vector3, Quaternion};
-use util::{ImageData, ImageDimension, Transform};
+use math::Color;
+use util::{ImageDimension, Tile, WorkerPool};
 use scene::Scene;
-use intersectable::{Sphere, Cube, Plane};
-use renderer::RayTracer;
-use camera::{Camera, ThirdPersonCamera};
-use object::Object as RenderObject;
+use camera::Camera;
+use renderer::{RenderOptions, sample_pixel};
+use film::Film;
+use aov::{AovBuffer, render_aovs};
+use output::{ImageFormat, save_film};
+use tonemap::ToneMapper;
+use scene_builder::JsScene;
+use convert::{camera_from_js, options_from_js, aovs_to_js};
+use validate::image_dimension_from_js;
 
 #[derive(Debug)]
 pub enum Event {
@@ -22,55 +30,233 @@ pub enum Event {
     h: usize,
     color: Color,
   },
+
+  /// Resolved RGBA pixels of whole rows, starting at row `y`
+  SetRows {
+    y: usize,
+    pixels: Vec<u8>,
+  },
   Update,
   Finish
 }
 
+/// Commands sent from JS to a running render thread
+pub enum Command {
+  UpdateCamera(Camera),
+  UpdateScene(Scene),
+  Shutdown,
+}
+
+/// Result of the last render, kept for saving
+pub struct Finished {
+  film: Film,
+  aovs: Vec<AovBuffer>,
+}
+
+/// What the render thread should do after draining its command queue
+enum Control {
+  Continue,
+  Restart,
+  Shutdown,
+}
+
+/// Apply every pending command. Several updates arriving in a burst (e.g.
+/// while dragging the mouse) collapse into a single restart.
+fn handle_commands(commands_rx: &mpsc::Receiver<Command>, scene: &mut Arc<Scene>, camera: &mut Arc<Camera>) -> Control {
+  let mut control = Control::Continue;
+  loop {
+    match commands_rx.try_recv() {
+      Ok(Command::UpdateCamera(new_camera)) => {
+        *camera = Arc::new(new_camera);
+        control = Control::Restart;
+      },
+      Ok(Command::UpdateScene(new_scene)) => {
+        *scene = prepare_scene(new_scene);
+        control = Control::Restart;
+      },
+      Ok(Command::Shutdown) | Err(TryRecvError::Disconnected) => return Control::Shutdown,
+      Err(TryRecvError::Empty) => return control,
+    }
+  }
+}
+
+/// Build the BVH of a scene before sharing it with the workers
+fn prepare_scene(mut scene: Scene) -> Arc<Scene> {
+  scene.build_bvh();
+  Arc::new(scene)
+}
+
+/// Tiles each worker takes on between two checks for new commands
+const TILES_PER_THREAD: usize = 256;
+
+/// Send the resolved colors of `rows` in a single event
+fn send_rows(tx: &mpsc::Sender<(usize, Event)>, gen: usize, film: &Film, tone_mapper: &ToneMapper, rows: Range<usize>) {
+  if rows.start >= rows.end {
+    return;
+  }
+  let mut pixels = Vec::with_capacity((rows.end - rows.start) * film.width * 4);
+  for y in rows.clone() {
+    for x in 0..film.width {
+      let color = tone_mapper.map(film.pixel(x, y), x, y);
+      pixels.extend_from_slice(&[color.r, color.g, color.b, color.a]);
+    }
+  }
+  tx.send((gen, Event::SetRows { y: rows.start, pixels })).expect("Send failed");
+}
+
 fn event_thread(
   scene: Scene,
   camera: Camera,
+  options: RenderOptions,
   img_dim: ImageDimension,
-  shutdown_rx: mpsc::Receiver<()>
-) -> mpsc::Receiver<Event> {
+  generation: Arc<AtomicUsize>,
+  finished: Arc<Mutex<Option<Finished>>>,
+  commands_rx: mpsc::Receiver<Command>
+) -> mpsc::Receiver<(usize, Event)> {
   let (tx, events_rx) = mpsc::channel();
   thread::spawn(move || {
-    for level in img_dim.levels() {
-
-      // Render the tiles
-      for tile in level.tiles() {
-        let ray = camera.ray(tile.x, tile.y, img_dim.width, img_dim.height);
-        // let color = match scene.lock().unwrap().intersect(&ray) {
-        let color = match scene.intersect(&ray) {
-          Some(itsct) => Color::from(itsct.normal),
-          None => Color::black()
-        };
-        tx.send(Event::SetPixels {
-          x: tile.x,
-          y: tile.y,
-          w: tile.w,
-          h: tile.h,
-          color
-        }).expect("Send failed");
-        // println!("Setting pixels {}, {}, {}, {}", tile.x, tile.y, tile.w, tile.h);
+    // The workers outlive every pass, the scene, camera and options being
+    // shared with them
+    let mut scene = prepare_scene(scene);
+    let mut camera = Arc::new(camera);
+    let options = Arc::new(options);
+    // A time budget without a sample target keeps refining until it runs
+    // out
+    let spp = match options.spp {
+      Some(spp) => spp.max(1),
+      None if options.time_budget.is_some() => usize::MAX,
+      None => 1,
+    };
+    let threads = options.threads.max(1);
+    let pool = WorkerPool::new(threads);
+    loop {
+
+      // Every event sent during this pass is tagged with the generation at
+      // the time of the (re)start so that stale ones can be dropped
+      let gen = generation.load(Ordering::SeqCst);
+      let start = Instant::now();
+      *finished.lock().expect("Film lock poisoned") = None;
+      let mut film = Film::new(img_dim.width, img_dim.height, options.filter.clone());
+      let mut control = Control::Continue;
+
+      'levels: for level in img_dim.levels() {
+
+        // Render the tiles. A level only yields the corners the coarser
+        // ones have not rendered yet, so every pixel gets its first sample
+        // exactly once. The tiles show that raw sample until the film gets
+        // resolved.
+        let tiles: Vec<Tile> = level.tiles().collect();
+        for batch in tiles.chunks(threads * TILES_PER_THREAD) {
+          let samples = {
+            let (scene, camera, options) = (Arc::clone(&scene), Arc::clone(&camera), Arc::clone(&options));
+            pool.map(batch.to_vec(), move |tile| {
+              let mut sampler = options.sampler.pixel_sampler(tile.x, tile.y, 0, spp);
+              sample_pixel(&scene, &camera, &options, tile.x, tile.y, &img_dim, &mut *sampler)
+            })
+          };
+
+          // The events follow the tile order whatever thread was faster
+          for (tile, (x, y, radiance)) in batch.iter().zip(samples) {
+            film.add_sample(x, y, radiance);
+            tx.send((gen, Event::SetPixels {
+              x: tile.x,
+              y: tile.y,
+              w: tile.w,
+              h: tile.h,
+              color: options.tone_mapper.map(radiance, tile.x, tile.y)
+            })).expect("Send failed");
+          }
+
+          // Check for camera/scene updates or shutdown signal
+          control = handle_commands(&commands_rx, &mut scene, &mut camera);
+          match control {
+            Control::Continue => {},
+            _ => break 'levels,
+          }
+        }
+
+        // Finished one level
+        tx.send((gen, Event::Update)).expect("Send failed");
       }
 
-      // Check for shutdown signal
-      match shutdown_rx.try_recv() {
-        Ok(_) | Err(TryRecvError::Disconnected) => { break; }
-        Err(TryRecvError::Empty) => {}
+      // Keep refining every pixel until the sample target or the time
+      // budget is reached. Rows are only sent once no later sample of the
+      // pass can reach them through the filter.
+      let mut samples = 1;
+      if let Control::Continue = control {
+        let lag = film.row_radius();
+        'passes: while samples < spp {
+          if options.time_budget.is_some_and(|budget| start.elapsed() >= budget) {
+            break;
+          }
+          let rows: Vec<usize> = (0..img_dim.height).collect();
+          let mut sent = 0;
+          for batch in rows.chunks(threads) {
+            let row_samples = {
+              let (scene, camera, options) = (Arc::clone(&scene), Arc::clone(&camera), Arc::clone(&options));
+              pool.map(batch.to_vec(), move |y| {
+                (0..img_dim.width).map(|x| {
+                  let mut sampler = options.sampler.pixel_sampler(x, y, samples, spp);
+                  sample_pixel(&scene, &camera, &options, x, y, &img_dim, &mut *sampler)
+                }).collect::<Vec<_>>()
+              })
+            };
+            for row in row_samples {
+              for (fx, fy, radiance) in row {
+                film.add_sample(fx, fy, radiance);
+              }
+            }
+            let ready = (batch[0] + batch.len()).saturating_sub(lag);
+            send_rows(&tx, gen, &film, &options.tone_mapper, sent..ready);
+            sent = ready;
+
+            control = handle_commands(&commands_rx, &mut scene, &mut camera);
+            match control {
+              Control::Continue => {},
+              _ => break 'passes,
+            }
+          }
+          send_rows(&tx, gen, &film, &options.tone_mapper, sent..img_dim.height);
+          samples += 1;
+
+          // Finished one pass
+          tx.send((gen, Event::Update)).expect("Send failed");
+        }
       }
 
-      // Finished one level
-      tx.send(Event::Update).expect("Send failed");
-      // println!("Updating");
+      match control {
+        Control::Restart => continue,
+        Control::Shutdown => return,
+        Control::Continue => {}
+      }
+
+      // Without any refining pass the screen still shows the raw tiles
+      if samples == 1 {
+        send_rows(&tx, gen, &film, &options.tone_mapper, 0..img_dim.height);
+        tx.send((gen, Event::Update)).expect("Send failed");
+      }
+
+      // Keep the film and the AOVs around for saving before announcing
+      // the finish
+      let aovs = render_aovs(&scene, &camera, &img_dim, &options.aovs, threads);
+      *finished.lock().expect("Film lock poisoned") = Some(Finished { film, aovs });
+      tx.send((gen, Event::Finish)).expect("Send failed");
+
+      // Idle until there is something new to render
+      match commands_rx.recv() {
+        Ok(Command::UpdateCamera(new_camera)) => camera = Arc::new(new_camera),
+        Ok(Command::UpdateScene(new_scene)) => scene = prepare_scene(new_scene),
+        Ok(Command::Shutdown) | Err(_) => return,
+      }
     }
-    tx.send(Event::Finish).expect("Send failed");
-    // println!("Finished");
   });
   events_rx
 }
 
-pub struct EventEmitterTask(Arc<Mutex<mpsc::Receiver<Event>>>);
+pub struct EventEmitterTask {
+  events: Arc<Mutex<mpsc::Receiver<(usize, Event)>>>,
+  generation: Arc<AtomicUsize>,
+}
 
 impl Task for EventEmitterTask {
   type Output = Option<Event>;
@@ -83,15 +269,20 @@ impl Task for EventEmitterTask {
   /// should only be executed one at a time by the `EventEmitter` class.
   fn perform(&self) -> Result<Self::Output, Self::Error> {
     let rx = self
-      .0
+      .events
       .lock()
       .map_err(|_| "Could not obtain lock on receiver".to_string())?;
 
-    // Attempt to read from the channel. Block for at most 100 ms.
-    match rx.recv_timeout(Duration::from_millis(100)) {
-      Ok(event) => Ok(Some(event)),
-      Err(RecvTimeoutError::Timeout) => Ok(None),
-      Err(RecvTimeoutError::Disconnected) => Err("Failed to receive event".to_string()),
+    // Attempt to read from the channel. Block for at most 100 ms. Events
+    // rendered for an outdated camera or scene are skipped.
+    loop {
+      match rx.recv_timeout(Duration::from_millis(100)) {
+        Ok((gen, event)) => if gen == self.generation.load(Ordering::SeqCst) {
+          return Ok(Some(event));
+        },
+        Err(RecvTimeoutError::Timeout) => return Ok(None),
+        Err(RecvTimeoutError::Disconnected) => return Err("Failed to receive event".to_string()),
+      }
     }
   }
 
@@ -104,7 +295,7 @@ impl Task for EventEmitterTask {
     event: Result<Self::Output, Self::Error>,
   ) -> JsResult<Self::JsEvent> {
     // Receive the event or return early with the error
-    let event = event.or_else(|err| cx.throw_error(&err.to_string()))?;
+    let event = event.or_else(|err| cx.throw_error(&err))?;
 
     // Timeout occured, return early with `undefined
     let event = match event {
@@ -147,6 +338,17 @@ impl Task for EventEmitterTask {
         o.set(&mut cx, "b", b).unwrap();
         o.set(&mut cx, "a", a).unwrap();
         o
+      },
+      Event::SetRows { y, pixels } => {
+        let o = cx.empty_object();
+        let event_type = cx.string("set_rows");
+        o.set(&mut cx, "type", event_type).unwrap();
+        let y = cx.number(y as f64);
+        let mut buffer = cx.buffer(pixels.len() as u32)?;
+        cx.borrow_mut(&mut buffer, |data| data.as_mut_slice::<u8>().copy_from_slice(&pixels));
+        o.set(&mut cx, "y", y).unwrap();
+        o.set(&mut cx, "pixels", buffer).unwrap();
+        o
       }
     };
 
@@ -155,8 +357,30 @@ impl Task for EventEmitterTask {
 }
 
 pub struct EventEmitter {
-  events: Arc<Mutex<mpsc::Receiver<Event>>>,
-  shutdown: mpsc::Sender<()>,
+  events: Arc<Mutex<mpsc::Receiver<(usize, Event)>>>,
+  commands: mpsc::Sender<Command>,
+  generation: Arc<AtomicUsize>,
+
+  /// Film and AOVs of the last finished render, `None` while rendering
+  finished: Arc<Mutex<Option<Finished>>>,
+
+  /// Tone mapping of the stream, applied to saved 8 bit images as well
+  tone_mapper: ToneMapper,
+}
+
+impl EventEmitter {
+  /// Send a command to the render thread. Updates invalidate every event
+  /// that has been emitted but not yet polled. Fails once the thread has
+  /// shut down.
+  fn send(&self, command: Command) -> Result<(), String> {
+    match command {
+      Command::UpdateCamera(_) | Command::UpdateScene(_) => {
+        self.generation.fetch_add(1, Ordering::SeqCst);
+      },
+      Command::Shutdown => {}
+    }
+    self.commands.send(command).map_err(|_| String::from("The render thread has shut down"))
+  }
 }
 
 declare_types! {
@@ -165,65 +389,41 @@ declare_types! {
 
       // Image Data
       let img_data: Handle<JsObject> = cx.argument::<JsObject>(0)?;
-      let width = img_data.get(&mut cx, "width")?.downcast::<JsNumber>().unwrap_or(cx.number(0)).value() as usize;
-      let height = img_data.get(&mut cx, "height")?.downcast::<JsNumber>().unwrap_or(cx.number(0)).value() as usize;
+      let img_dim = image_dimension_from_js(&mut cx, img_data)?;
       // let mut buffer = img_data.get(&mut cx, "data")?.downcast::<JsBuffer>().unwrap_or(cx.buffer(0)?);
 
       // Set this
       // let this = cx.this();
       // this.set(&mut cx, "img_data", img_data)?;
 
-      // Camera
-      let camera: Handle<JsObject> = cx.argument::<JsObject>(1)?;
-      let target = camera.get(&mut cx, "target")?.downcast::<JsObject>().unwrap_or(JsObject::new(&mut cx));
-      let target_x = target.get(&mut cx, "x")?.downcast::<JsNumber>().unwrap_or(cx.number(0)).value() as f32;
-      let target_y = target.get(&mut cx, "y")?.downcast::<JsNumber>().unwrap_or(cx.number(0)).value() as f32;
-      let target_z = target.get(&mut cx, "z")?.downcast::<JsNumber>().unwrap_or(cx.number(0)).value() as f32;
-      let azimuth = camera.get(&mut cx, "azimuth")?.downcast::<JsNumber>().unwrap_or(cx.number(0)).value() as f32;
-      let incline = camera.get(&mut cx, "incline")?.downcast::<JsNumber>().unwrap_or(cx.number(0)).value() as f32;
-      let distance = camera.get(&mut cx, "distance")?.downcast::<JsNumber>().unwrap_or(cx.number(0)).value() as f32;
-      let tpc = ThirdPersonCamera {
-        target: vec3!(target_x, target_y, target_z),
-        azimuth,
-        incline,
-        distance,
-      };
-
       // Scene
-      let scene = Scene {
-        objects: vec![
-          RenderObject {
-            transform: Transform {
-              position: vec3!(0.0, 0.15, 0.0),
-              scale: vec3!(1.0, 1.0, 1.0),
-              rotation: Quaternion::axis_angle(vec3!(0.0, 1.0, 0.0), 3.14),
-            },
-            intersectable: Box::new(Sphere::new(0.3))
-          },
-          RenderObject {
-            transform: Transform {
-              position: vec3!(0.0),
-              scale: vec3!(1.0, 1.0, 1.0),
-              rotation: Quaternion::identity(),
-            },
-            intersectable: Box::new(Plane::new())
-          }
-        ]
-      };
+      let scene = cx.argument::<JsScene>(1)?;
+      let scene = cx.borrow(&scene, |scene| scene.clone());
+
+      // Camera
+      let camera: Handle<JsObject> = cx.argument::<JsObject>(2)?;
+      let camera = camera_from_js(&mut cx, camera)?;
 
-      let camera = Camera::third_person(&tpc);
+      // Render options
+      let options = cx.argument_opt(3);
+      let options = options_from_js(&mut cx, options)?;
 
-      let (shutdown, shutdown_rx) = mpsc::channel();
+      let (commands, commands_rx) = mpsc::channel();
 
-      let img_dim = ImageDimension { width, height };
+      let generation = Arc::new(AtomicUsize::new(0));
+      let finished = Arc::new(Mutex::new(None));
+      let tone_mapper = options.tone_mapper;
 
       // Start work in a separate thread
-      let rx = event_thread(scene, camera, img_dim, shutdown_rx);
+      let rx = event_thread(scene, camera, options, img_dim, Arc::clone(&generation), Arc::clone(&finished), commands_rx);
 
       // Construct a new `EventEmitter` to be wrapped by the class.
       Ok(EventEmitter {
         events: Arc::new(Mutex::new(rx)),
-        shutdown,
+        commands,
+        generation,
+        finished,
+        tone_mapper,
       })
     }
 
@@ -236,8 +436,10 @@ declare_types! {
       let this = cx.this();
 
       // Create an asynchronously `EventEmitterTask` to receive data
-      let events = cx.borrow(&this, |emitter| Arc::clone(&emitter.events));
-      let emitter = EventEmitterTask(events);
+      let emitter = cx.borrow(&this, |emitter| EventEmitterTask {
+        events: Arc::clone(&emitter.events),
+        generation: Arc::clone(&emitter.generation),
+      });
 
       // Schedule the task on the `libuv` thread pool
       emitter.schedule(cb);
@@ -246,14 +448,78 @@ declare_types! {
       Ok(JsUndefined::new().upcast())
     }
 
+    // Point the stream at a new camera. The thread abandons the current
+    // pass and restarts progressive refinement from the coarsest level.
+    method updateCamera(mut cx) {
+      let camera: Handle<JsObject> = cx.argument::<JsObject>(0)?;
+      let camera = camera_from_js(&mut cx, camera)?;
+      let this = cx.this();
+
+      cx.borrow(&this, |emitter| emitter.send(Command::UpdateCamera(camera)))
+          .or_else(|err| cx.throw_error(err))?;
+
+      Ok(JsUndefined::new().upcast())
+    }
+
+    // Same as `updateCamera`, but swaps in a snapshot of the given scene.
+    method updateScene(mut cx) {
+      let scene = cx.argument::<JsScene>(0)?;
+      let scene = cx.borrow(&scene, |scene| scene.clone());
+      let this = cx.this();
+
+      cx.borrow(&this, |emitter| emitter.send(Command::UpdateScene(scene)))
+          .or_else(|err| cx.throw_error(err))?;
+
+      Ok(JsUndefined::new().upcast())
+    }
+
+    // Save the finished render to a file. The format is one of `"png"`,
+    // `"ppm"`, `"pfm"`, `"exr"` or `"exr32"`, guessed from the extension
+    // when omitted.
+    method saveImage(mut cx) {
+      let path = cx.argument::<JsString>(0)?.value();
+      let path = PathBuf::from(path);
+      let format = match cx.argument_opt(1).map(|format| format.downcast::<JsString>()) {
+        Some(Ok(format)) => ImageFormat::from_name(&format.value()),
+        _ => ImageFormat::from_path(&path),
+      };
+      let format = match format {
+        Some(format) => format,
+        None => return cx.throw_type_error(format!("Unknown image format for \"{}\"", path.display())),
+      };
+      let this = cx.this();
+
+      let (finished, tone_mapper) = cx.borrow(&this, |emitter| (Arc::clone(&emitter.finished), emitter.tone_mapper));
+      let finished = finished.lock().expect("Film lock poisoned");
+      let result = match *finished {
+        Some(ref finished) => save_film(&finished.film, &finished.aovs, &tone_mapper, &path, format),
+        None => return cx.throw_error("The render has not finished yet"),
+      };
+      result.or_else(|err| cx.throw_error(format!("{}: {}", path.display(), err)))?;
+
+      Ok(JsUndefined::new().upcast())
+    }
+
+    // AOVs of the finished render as an object of `ArrayBuffer`s holding
+    // floats, keyed by AOV name
+    method aovs(mut cx) {
+      let this = cx.this();
+      let finished = cx.borrow(&this, |emitter| Arc::clone(&emitter.finished));
+      let aovs = match *finished.lock().expect("Film lock poisoned") {
+        Some(ref finished) => finished.aovs.clone(),
+        None => return cx.throw_error("The render has not finished yet"),
+      };
+      Ok(aovs_to_js(&mut cx, &aovs)?.upcast())
+    }
+
     // The shutdown method may be called to stop the Rust thread. It
     // will error if the thread has already been destroyed.
     method shutdown(mut cx) {
       let this = cx.this();
 
-      // Unwrap the shutdown channel and send a shutdown command
-      cx.borrow(&this, |emitter| emitter.shutdown.send(()))
-          .or_else(|err| cx.throw_error(&err.to_string()))?;
+      // Send a shutdown command through the command channel
+      cx.borrow(&this, |emitter| emitter.send(Command::Shutdown))
+          .or_else(|err| cx.throw_error(err))?;
 
       Ok(JsUndefined::new().upcast())
     }
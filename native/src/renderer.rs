@@ -1,18 +1,261 @@
-use ::math::{Color};
-use ::util::{ImageData, Intersection};
+use std::error;
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+use ::math::Spectrum;
+use ::util::{ImageData, ImageDimension, default_threads, parallel_map};
 use ::scene::Scene;
 use ::camera::Camera;
+use ::sampler::{Sampler, SamplerKind};
+use ::filter::{Filter, BoxFilter, FilterDescriptor};
+use ::film::Film;
+use ::tonemap::{ToneMapper, ToneMapOperator};
+use ::aov::Aov;
+use ::integrator::{Integrator, NormalIntegrator, DepthIntegrator, AmbientOcclusionIntegrator, WhittedIntegrator, PathTracer};
+
+/// Rows each worker of `render` takes on before the samples get splatted
+const ROWS_PER_THREAD: usize = 4;
+
+/// Settings shared by the blocking `render` export and `RenderStream`
+#[derive(Clone)]
+pub struct RenderOptions {
+  pub integrator: Arc<dyn Integrator>,
+
+  /// Samples per pixel, 1 when not given. `RenderStream` keeps
+  /// accumulating samples after its first full resolution pass until
+  /// reaching this target, or until the time budget runs out if only that
+  /// is given.
+  pub spp: Option<usize>,
+
+  /// Wall clock time after which `RenderStream` stops taking new samples,
+  /// measured from the last (re)start
+  pub time_budget: Option<Duration>,
+
+  /// Strategy placing the samples within pixels, the lens and beyond
+  pub sampler: SamplerKind,
+
+  /// Reconstruction filter the samples get splatted into the film with
+  pub filter: Arc<dyn Filter>,
+
+  /// Worker threads the samples get spread over
+  pub threads: usize,
+
+  /// Conversion of the film radiance into the displayed 8 bit colors
+  pub tone_mapper: ToneMapper,
+
+  /// Extra buffers to render alongside the color
+  pub aovs: Vec<Aov>,
+}
+
+impl Default for RenderOptions {
+  fn default() -> Self {
+    Self {
+      integrator: Arc::new(WhittedIntegrator { max_depth: 5 }),
+      spp: None,
+      time_budget: None,
+      sampler: SamplerKind::Random,
+      filter: Arc::new(BoxFilter { radius: 0.5 }),
+      threads: default_threads(),
+      tone_mapper: ToneMapper::new(),
+      aovs: vec![],
+    }
+  }
+}
+
+impl RenderOptions {
+  pub fn new() -> Self {
+    Self::default()
+  }
+}
+
+/// Render settings of scene files. Mirrors the options object of the JS
+/// bindings, adding the image size.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase", deny_unknown_fields)]
+pub struct RenderSettings {
+  pub width: usize,
+  pub height: usize,
+
+  /// `"normal"`, `"depth"`, `"ao"`, `"whitted"` or `"path"`
+  pub integrator: String,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub max_depth: Option<usize>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub max_distance: Option<f32>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub ao_samples: Option<usize>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub ao_radius: Option<f32>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub spp: Option<usize>,
+
+  /// In milliseconds
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub time_budget: Option<u64>,
+  pub sampler: SamplerKind,
+  pub filter: FilterDescriptor,
+
+  /// Defaults to the number of logical cores
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub threads: Option<usize>,
+  pub exposure: f32,
+  pub tone_map: ToneMapOperator,
+  pub srgb: bool,
+  pub dither: bool,
+  #[serde(skip_serializing_if = "Vec::is_empty")]
+  pub aovs: Vec<Aov>,
+}
+
+impl Default for RenderSettings {
+  fn default() -> Self {
+    Self {
+      width: 640,
+      height: 480,
+      integrator: String::from("whitted"),
+      max_depth: None,
+      max_distance: None,
+      ao_samples: None,
+      ao_radius: None,
+      spp: None,
+      time_budget: None,
+      sampler: SamplerKind::Random,
+      filter: FilterDescriptor::default(),
+      threads: None,
+      exposure: 0.0,
+      tone_map: ToneMapOperator::Linear,
+      srgb: true,
+      dither: true,
+      aovs: vec![],
+    }
+  }
+}
+
+/// Why render settings cannot be turned into options
+#[derive(Debug)]
+pub enum SettingsError {
+  UnknownIntegrator(String),
+
+  /// Field, as named in scene files, out of its range along with what it
+  /// should be
+  OutOfRange(&'static str, &'static str),
+}
+
+impl fmt::Display for SettingsError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      SettingsError::UnknownIntegrator(name) => {
+        write!(f, "unknown integrator \"{}\", expected normal, depth, ao, whitted or path", name)
+      },
+      SettingsError::OutOfRange(key, expectation) => write!(f, "expected {} to be {}", key, expectation),
+    }
+  }
+}
+
+impl error::Error for SettingsError {}
+
+impl RenderSettings {
+  pub fn to_options(&self) -> Result<RenderOptions, SettingsError> {
+    let positive = |key, value: Option<f32>| match value {
+      Some(value) if value <= 0.0 || value.is_nan() => Err(SettingsError::OutOfRange(key, "positive")),
+      _ => Ok(()),
+    };
+    positive("maxDistance", self.max_distance)?;
+    positive("aoRadius", self.ao_radius)?;
+    match self.filter {
+      FilterDescriptor::Box { radius } | FilterDescriptor::Tent { radius } | FilterDescriptor::Mitchell { radius, .. } => {
+        positive("filter.radius", radius)?;
+      },
+      FilterDescriptor::Gaussian { radius, alpha } => {
+        positive("filter.radius", radius)?;
+        positive("filter.alpha", alpha)?;
+      },
+    }
+    if self.spp == Some(0) {
+      return Err(SettingsError::OutOfRange("spp", "at least 1"));
+    }
+    if self.threads == Some(0) {
+      return Err(SettingsError::OutOfRange("threads", "at least 1"));
+    }
+    let integrator: Arc<dyn Integrator> = match self.integrator.as_str() {
+      "normal" => Arc::new(NormalIntegrator),
+      "depth" => Arc::new(DepthIntegrator { max_distance: self.max_distance.unwrap_or(10.0) }),
+      "ao" => Arc::new(AmbientOcclusionIntegrator {
+        samples: self.ao_samples.unwrap_or(16).max(1),
+        radius: self.ao_radius.unwrap_or(1.0),
+      }),
+      "whitted" => Arc::new(WhittedIntegrator { max_depth: self.max_depth.unwrap_or(5) }),
+      "path" => Arc::new(PathTracer::new(self.max_depth.unwrap_or(8))),
+      _ => return Err(SettingsError::UnknownIntegrator(self.integrator.clone())),
+    };
+    Ok(RenderOptions {
+      integrator,
+      spp: self.spp,
+      time_budget: self.time_budget.map(Duration::from_millis),
+      sampler: self.sampler,
+      filter: self.filter.build(),
+      threads: self.threads.unwrap_or_else(default_threads),
+      tone_mapper: ToneMapper {
+        exposure: self.exposure,
+        operator: self.tone_map,
+        srgb: self.srgb,
+        dither: self.dither,
+      },
+      aovs: self.aovs.clone(),
+    })
+  }
+}
+
+/// Take one sample within the pixel `(x, y)`. Returns the film position it
+/// was taken at along with the incoming radiance.
+pub fn sample_pixel(
+  scene: &Scene,
+  camera: &Camera,
+  options: &RenderOptions,
+  x: usize,
+  y: usize,
+  img_dim: &ImageDimension,
+  sampler: &mut dyn Sampler
+) -> (f32, f32, Spectrum) {
+  let (u0, u1) = sampler.next_2d();
+  let (fx, fy) = (x as f32 + u0, y as f32 + u1);
+  let ray = camera.film_ray(fx, fy, img_dim.width, img_dim.height);
+  let ray = camera.thin_lens(&ray, sampler.next_2d());
+  (fx, fy, options.integrator.li(&ray, scene, sampler))
+}
 
-pub struct RayTracer;
+/// Render the whole image, filtering `spp` samples in every pixel
+pub fn render(scene: &Scene, camera: &Camera, img_data: &mut ImageData, options: &RenderOptions) {
+  let img_dim = ImageDimension { width: img_data.width, height: img_data.height };
+  render_film(scene, camera, &img_dim, options, |_| {}).write_image(img_data, &options.tone_mapper);
+}
 
-impl RayTracer {
-  pub fn render(scene: &Scene, camera: &Camera, img_data: &mut ImageData) {
-    for (i, j, ray) in camera.rays(img_data.width, img_data.height) {
-      let color = match scene.intersect(&ray) {
-        Some(itsct) => Color::from(itsct.normal), // TODO: Change the colors
-        None => Color::black()
-      };
-      img_data.set_pixel(i, j, &color);
+/// Same as `render`, keeping the full precision film, e.g. to save it. Rows
+/// are spread over the worker threads a few at a time and splatted in order,
+/// so the result does not depend on the number of threads. `progress` gets
+/// the number of finished rows after every batch.
+pub fn render_film<F>(scene: &Scene, camera: &Camera, img_dim: &ImageDimension, options: &RenderOptions, mut progress: F) -> Film
+  where F: FnMut(usize)
+{
+  let spp = options.spp.unwrap_or(1).max(1);
+  let threads = options.threads.max(1);
+  let mut film = Film::new(img_dim.width, img_dim.height, options.filter.clone());
+  let rows: Vec<usize> = (0..img_dim.height).collect();
+  for batch in rows.chunks(threads * ROWS_PER_THREAD) {
+    let row_samples = parallel_map(threads, batch, |&y| {
+      let mut samples = Vec::with_capacity(img_dim.width * spp);
+      for x in 0..img_dim.width {
+        for index in 0..spp {
+          let mut sampler = options.sampler.pixel_sampler(x, y, index, spp);
+          samples.push(sample_pixel(scene, camera, options, x, y, img_dim, &mut *sampler));
+        }
+      }
+      samples
+    });
+    for (fx, fy, radiance) in row_samples.into_iter().flatten() {
+      film.add_sample(fx, fy, radiance);
     }
+    progress(batch[batch.len() - 1] + 1);
   }
-}
\ No newline at end of file
+  film
+}
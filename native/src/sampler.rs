@@ -0,0 +1,338 @@
+/// Source of sample values in `[0, 1)`. Every call consumes the next
+/// dimension(s) of the current sample, so consumers must request them in a
+/// consistent order for low discrepancy samplers to work well.
+pub trait Sampler {
+  fn next_1d(&mut self) -> f32;
+
+  fn next_2d(&mut self) -> (f32, f32) {
+    let u0 = self.next_1d();
+    let u1 = self.next_1d();
+    (u0, u1)
+  }
+}
+
+/// Independent uniform samples from a PCG32 generator
+#[derive(Clone)]
+pub struct RandomSampler {
+  state: u64,
+  inc: u64,
+}
+
+impl RandomSampler {
+  pub fn new(seed: u64) -> Self {
+    let mut sampler = Self { state: 0, inc: (seed << 1) | 1 };
+    sampler.next_u32();
+    sampler.state = sampler.state.wrapping_add(0x853c_49e6_748f_ea9b);
+    sampler.next_u32();
+    sampler
+  }
+
+  /// Seed that decorrelates the pixel `(x, y)` across passes
+  pub fn for_pixel(x: usize, y: usize, pass: usize) -> Self {
+    let seed = (x as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15)
+      ^ (y as u64).wrapping_mul(0xc2b2_ae3d_27d4_eb4f)
+      ^ (pass as u64).wrapping_mul(0x1656_67b1_9e37_79f9);
+    Self::new(seed)
+  }
+
+  pub fn next_u32(&mut self) -> u32 {
+    let old = self.state;
+    self.state = old.wrapping_mul(6_364_136_223_846_793_005).wrapping_add(self.inc);
+    let xorshifted = (((old >> 18) ^ old) >> 27) as u32;
+    let rot = (old >> 59) as u32;
+    xorshifted.rotate_right(rot)
+  }
+}
+
+impl Sampler for RandomSampler {
+  fn next_1d(&mut self) -> f32 {
+    // Use the upper 24 bits so that the result is strictly below 1
+    (self.next_u32() >> 8) as f32 * (1.0 / (1u32 << 24) as f32)
+  }
+}
+
+/// The sampling strategies selectable in the render options
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SamplerKind {
+  Random,
+  Stratified,
+  Halton,
+  Sobol,
+}
+
+impl SamplerKind {
+  /// Parse `"random"`, `"stratified"`, `"halton"` or `"sobol"`
+  pub fn from_name(name: &str) -> Option<Self> {
+    match name {
+      "random" => Some(SamplerKind::Random),
+      "stratified" => Some(SamplerKind::Stratified),
+      "halton" => Some(SamplerKind::Halton),
+      "sobol" => Some(SamplerKind::Sobol),
+      _ => None,
+    }
+  }
+
+  /// Sampler producing sample `index` out of `count` for the pixel `(x, y)`
+  pub fn pixel_sampler(&self, x: usize, y: usize, index: usize, count: usize) -> Box<dyn Sampler> {
+    let seed = pixel_seed(x, y);
+    match self {
+      SamplerKind::Random => Box::new(RandomSampler::for_pixel(x, y, index)),
+      SamplerKind::Stratified => Box::new(StratifiedSampler::new(seed, x, y, index, count)),
+      SamplerKind::Halton => Box::new(HaltonSampler::new(seed, x, y, index)),
+      SamplerKind::Sobol => Box::new(SobolSampler::new(seed, index, count)),
+    }
+  }
+}
+
+/// Finalizer of SplitMix64, scrambling the bits of `x`
+fn mix(x: u64) -> u64 {
+  let x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+  let x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+  x ^ (x >> 31)
+}
+
+fn pixel_seed(x: usize, y: usize) -> u64 {
+  mix((x as u64) << 32 | y as u64)
+}
+
+/// Seed shared by every sample of a pixel in the given dimension
+fn dimension_seed(seed: u64, dimension: u32) -> u32 {
+  mix(seed ^ (dimension as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15)) as u32
+}
+
+/// Largest `f32` below 1
+const ONE_MINUS_EPSILON: f32 = 1.0 - f32::EPSILON / 2.0;
+
+fn u32_to_unit(bits: u32) -> f32 {
+  (bits >> 8) as f32 * (1.0 / (1u32 << 24) as f32)
+}
+
+/// Kensler's hashed permutation: maps `i` in `[0, len)` to a position in a
+/// random permutation of `[0, len)` chosen by `seed`, without storing it
+fn permute(mut i: u32, len: u32, seed: u32) -> u32 {
+  let mut w = len - 1;
+  w |= w >> 1;
+  w |= w >> 2;
+  w |= w >> 4;
+  w |= w >> 8;
+  w |= w >> 16;
+  loop {
+    i ^= seed;
+    i = i.wrapping_mul(0xe170_893d);
+    i ^= seed >> 16;
+    i ^= (i & w) >> 4;
+    i ^= seed >> 8;
+    i = i.wrapping_mul(0x0929_eb3f);
+    i ^= seed >> 23;
+    i ^= (i & w) >> 1;
+    i = i.wrapping_mul(1 | seed >> 27);
+    i = i.wrapping_mul(0x6935_fa69);
+    i ^= (i & w) >> 11;
+    i = i.wrapping_mul(0x74dc_b303);
+    i ^= (i & w) >> 2;
+    i = i.wrapping_mul(0x9e50_1cc3);
+    i ^= (i & w) >> 2;
+    i = i.wrapping_mul(0xc860_a3df);
+    i &= w;
+    i ^= i >> 5;
+    if i < len {
+      break;
+    }
+  }
+  i.wrapping_add(seed) % len
+}
+
+/// Jittered stratification: the `count` samples of a pixel fall into
+/// distinct strata of every dimension (or pair of dimensions), the strata
+/// being shuffled independently per dimension
+pub struct StratifiedSampler {
+  seed: u64,
+  index: u32,
+  count: u32,
+  dimension: u32,
+  jitter: RandomSampler,
+}
+
+impl StratifiedSampler {
+  pub fn new(seed: u64, x: usize, y: usize, index: usize, count: usize) -> Self {
+    // Sample indices past `count` wrap around to another set of strata
+    let count = count.clamp(1, 1 << 16) as u32;
+    Self { seed, index: index as u32 % count, count, dimension: 0, jitter: RandomSampler::for_pixel(x, y, index) }
+  }
+}
+
+impl Sampler for StratifiedSampler {
+  fn next_1d(&mut self) -> f32 {
+    let stratum = permute(self.index, self.count, dimension_seed(self.seed, self.dimension));
+    self.dimension += 1;
+
+    // The sum may round up to `count` when the jitter is close to 1
+    ((stratum as f32 + self.jitter.next_1d()) / self.count as f32).min(ONE_MINUS_EPSILON)
+  }
+
+  fn next_2d(&mut self) -> (f32, f32) {
+    let n = (self.count as f32).sqrt().ceil() as u32;
+    let cell = permute(self.index, n * n, dimension_seed(self.seed, self.dimension));
+    self.dimension += 2;
+    let (cx, cy) = (cell % n, cell / n);
+    let (jx, jy) = self.jitter.next_2d();
+    (((cx as f32 + jx) / n as f32).min(ONE_MINUS_EPSILON), ((cy as f32 + jy) / n as f32).min(ONE_MINUS_EPSILON))
+  }
+}
+
+const PRIMES: [u32; 32] = [
+  2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53,
+  59, 61, 67, 71, 73, 79, 83, 89, 97, 101, 103, 107, 109, 113, 127, 131,
+];
+
+fn radical_inverse(base: u32, mut index: u64) -> f32 {
+  let inv_base = 1.0 / base as f64;
+  let mut inv_base_n = 1.0;
+  let mut reversed = 0.0;
+  while index > 0 {
+    let digit = index % base as u64;
+    reversed = reversed * base as f64 + digit as f64;
+    inv_base_n *= inv_base;
+    index /= base as u64;
+  }
+  ((reversed * inv_base_n) as f32).min(1.0 - f32::EPSILON)
+}
+
+/// Halton sequence, one prime base per dimension, decorrelated between
+/// pixels by a random toroidal shift. Dimensions past the supported number of
+/// bases fall back to independent random numbers.
+pub struct HaltonSampler {
+  seed: u64,
+  index: u64,
+  dimension: u32,
+  fallback: RandomSampler,
+}
+
+impl HaltonSampler {
+  pub fn new(seed: u64, x: usize, y: usize, index: usize) -> Self {
+    Self { seed, index: index as u64, dimension: 0, fallback: RandomSampler::for_pixel(x, y, index) }
+  }
+}
+
+impl Sampler for HaltonSampler {
+  fn next_1d(&mut self) -> f32 {
+    let dimension = self.dimension;
+    self.dimension += 1;
+    if dimension as usize >= PRIMES.len() {
+      return self.fallback.next_1d();
+    }
+    let shift = u32_to_unit(dimension_seed(self.seed, dimension));
+    let value = radical_inverse(PRIMES[dimension as usize], self.index) + shift;
+    if value >= 1.0 { value - 1.0 } else { value }
+  }
+}
+
+/// First two dimensions of the Sobol sequence, i.e. a (0, 2)-sequence,
+/// applied to every pair of dimensions with an independent index shuffle
+/// and random digit scrambling. Pixels use power of two sample counts.
+pub struct SobolSampler {
+  seed: u64,
+  index: u32,
+  count: u32,
+  dimension: u32,
+}
+
+impl SobolSampler {
+  pub fn new(seed: u64, index: usize, count: usize) -> Self {
+    let count = count.clamp(1, 1 << 16).next_power_of_two() as u32;
+    Self { seed, index: index as u32 % count, count, dimension: 0 }
+  }
+
+  fn shuffled_index(&self) -> u32 {
+    permute(self.index, self.count, dimension_seed(self.seed, self.dimension) ^ 0x5bd1_e995)
+  }
+}
+
+/// Sobol points as 32 bit fixed point fractions
+fn sobol_2d(mut index: u32) -> (u32, u32) {
+  let (mut v0, mut v1) = (1u32 << 31, 1u32 << 31);
+  let (mut r0, mut r1) = (0, 0);
+  while index != 0 {
+    if index & 1 != 0 {
+      r0 ^= v0;
+      r1 ^= v1;
+    }
+    index >>= 1;
+    v0 >>= 1;
+    v1 ^= v1 >> 1;
+  }
+  (r0, r1)
+}
+
+impl Sampler for SobolSampler {
+  fn next_1d(&mut self) -> f32 {
+    let (bits, _) = sobol_2d(self.shuffled_index());
+    let scramble = dimension_seed(self.seed, self.dimension);
+    self.dimension += 1;
+    u32_to_unit(bits ^ scramble)
+  }
+
+  fn next_2d(&mut self) -> (f32, f32) {
+    let (b0, b1) = sobol_2d(self.shuffled_index());
+    let s0 = dimension_seed(self.seed, self.dimension);
+    let s1 = dimension_seed(self.seed, self.dimension + 1);
+    self.dimension += 2;
+    (u32_to_unit(b0 ^ s0), u32_to_unit(b1 ^ s1))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const KINDS: [SamplerKind; 4] = [SamplerKind::Random, SamplerKind::Stratified, SamplerKind::Halton, SamplerKind::Sobol];
+
+  #[test]
+  fn samples_stay_in_unit_interval() {
+    for kind in &KINDS {
+      for &count in &[1, 7, 16, 100] {
+        for index in 0..count {
+          let mut sampler = kind.pixel_sampler(index % 5, index / 5, index, count);
+          for _ in 0..40 {
+            let u = sampler.next_1d();
+            let (u0, u1) = sampler.next_2d();
+            for &u in &[u, u0, u1] {
+              assert!((0.0..1.0).contains(&u), "{:?} gave {}", kind, u);
+            }
+          }
+        }
+      }
+    }
+  }
+
+  /// Whether the `count` samples of a pixel fall into distinct strata, in
+  /// one dimension and on a grid in two
+  fn assert_stratified(kind: SamplerKind, count: usize) {
+    let n = (count as f32).sqrt() as usize;
+    let mut strata = vec![false; count];
+    let mut cells = vec![false; count];
+    for index in 0..count {
+      let mut sampler = kind.pixel_sampler(3, 8, index, count);
+      let stratum = (sampler.next_1d() * count as f32) as usize;
+      assert!(!strata[stratum], "{:?} put two samples into stratum {}", kind, stratum);
+      strata[stratum] = true;
+      let (u0, u1) = sampler.next_2d();
+      let cell = (u1 * n as f32) as usize * n + (u0 * n as f32) as usize;
+      assert!(!cells[cell], "{:?} put two samples into cell {}", kind, cell);
+      cells[cell] = true;
+    }
+  }
+
+  #[test]
+  fn stratified_samples_cover_every_stratum() {
+    assert_stratified(SamplerKind::Stratified, 16);
+    assert_stratified(SamplerKind::Stratified, 64);
+  }
+
+  #[test]
+  fn sobol_samples_cover_every_stratum() {
+    assert_stratified(SamplerKind::Sobol, 16);
+    assert_stratified(SamplerKind::Sobol, 64);
+  }
+}
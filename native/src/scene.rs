@@ -1,19 +1,488 @@
-use ::object::Object;
-use ::util::{Ray, Intersection};
+use serde::{Serialize, Serializer};
+use std::convert::TryFrom;
+use std::sync::Arc;
 
-#[derive(Clone)]
+use ::math::{Vector3, Spectrum, Matrix4};
+use ::object::{Object, Group};
+use ::intersectable::ShapeDescriptor;
+use ::material::{Material, Lambertian};
+use ::instance::{Prototype, PrototypeDescriptor};
+use ::light::Light;
+use ::util::{Ray, Intersection, Transform, Placement, RAY_EPSILON};
+use ::bvh::Bvh;
+
+#[derive(Clone, Default, Deserialize)]
+#[serde(try_from = "SceneContents")]
 pub struct Scene {
   pub objects: Vec<Object>,
+
+  /// Geometry shared by instances, with unique names
+  pub prototypes: Vec<Arc<Prototype>>,
+
+  /// Groups the objects hang from, in no particular order
+  pub groups: Vec<Group>,
+  pub lights: Vec<Box<dyn Light + Send>>,
+
+  /// Id given to the next object added. Ids are never reused, so that a
+  /// stale id cannot refer to some other object.
+  #[serde(skip)]
+  next_id: usize,
+
+  /// BVH over the bounded objects, dropped whenever objects or groups
+  /// change
+  #[serde(skip)]
+  bvh: Option<Bvh>,
+
+  /// World placement of every object, built along with the BVH. `None`
+  /// for objects flattened by a zero scale, which are left out of it.
+  #[serde(skip)]
+  world: Vec<Option<Placement>>,
+
+  /// Objects with infinite bounds (e.g. planes), tested one by one
+  #[serde(skip)]
+  unbounded: Vec<usize>,
+}
+
+/// What scene files store of a scene, with the objects and groups of each
+/// group nested into it. Ids are not part of it, but handed out again in
+/// file order when loading: root objects first, then every group followed
+/// by its objects and its own groups. Prototypes come first so that the
+/// instances of objects, and of later prototypes, can refer to them.
+#[derive(Deserialize)]
+struct SceneContents {
+  #[serde(default)]
+  prototypes: Vec<PrototypeDescriptor>,
+  #[serde(default)]
+  objects: Vec<ObjectContents>,
+  #[serde(default)]
+  groups: Vec<GroupContents>,
+  #[serde(default)]
+  lights: Vec<Box<dyn Light + Send>>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct GroupContents {
+  #[serde(default)]
+  name: Option<String>,
+  #[serde(default)]
+  transform: Transform,
+  #[serde(default)]
+  objects: Vec<ObjectContents>,
+  #[serde(default)]
+  groups: Vec<GroupContents>,
+}
+
+/// Object with its shape yet to be built
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ObjectContents {
+  #[serde(default)]
+  name: Option<String>,
+  #[serde(default)]
+  transform: Transform,
+  intersectable: ShapeDescriptor,
+  #[serde(default = "default_material")]
+  material: Box<dyn Material + Send>,
+}
+
+/// Material of scene file objects that do not name one
+fn default_material() -> Box<dyn Material + Send> {
+  Box::new(Lambertian::new(vec3!(0.8)))
+}
+
+impl TryFrom<SceneContents> for Scene {
+  type Error = String;
+
+  fn try_from(contents: SceneContents) -> Result<Self, String> {
+    let mut scene = Scene::new();
+    for (index, prototype) in contents.prototypes.into_iter().enumerate() {
+      let prototype = prototype.build(&scene.prototypes, &format!("prototypes[{}]", index))?;
+      scene.add_prototype(prototype)?;
+    }
+    for (index, object) in contents.objects.into_iter().enumerate() {
+      scene.add_object_contents(None, object, &format!("objects[{}]", index))?;
+    }
+    for (index, group) in contents.groups.into_iter().enumerate() {
+      scene.add_group_contents(None, group, &format!("groups[{}]", index))?;
+    }
+    scene.lights = contents.lights;
+    Ok(scene)
+  }
+}
+
+/// Borrowed counterpart of `SceneContents` for saving
+#[derive(Serialize)]
+struct SceneView<'a> {
+  #[serde(skip_serializing_if = "Vec::is_empty")]
+  prototypes: Vec<PrototypeDescriptor>,
+  objects: Vec<&'a Object>,
+  #[serde(skip_serializing_if = "Vec::is_empty")]
+  groups: Vec<GroupView<'a>>,
+  lights: &'a [Box<dyn Light + Send>],
+}
+
+#[derive(Serialize)]
+struct GroupView<'a> {
+  #[serde(skip_serializing_if = "Option::is_none")]
+  name: Option<&'a str>,
+  transform: &'a Transform,
+  #[serde(skip_serializing_if = "Vec::is_empty")]
+  objects: Vec<&'a Object>,
+  #[serde(skip_serializing_if = "Vec::is_empty")]
+  groups: Vec<GroupView<'a>>,
+}
+
+impl Serialize for Scene {
+  fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+    let view = SceneView {
+      prototypes: self.prototypes.iter().map(|prototype| prototype.describe()).collect(),
+      objects: self.objects.iter().filter(|object| object.parent.is_none()).collect(),
+      groups: self.group_views(None),
+      lights: &self.lights,
+    };
+    view.serialize(serializer)
+  }
 }
 
 impl Scene {
   pub fn new() -> Self {
-    Scene { objects: vec![] }
+    Self::default()
+  }
+
+  /// `path` locates the contents in error messages, e.g. `groups[0].objects[1]`
+  fn add_object_contents(&mut self, parent: Option<usize>, contents: ObjectContents, path: &str) -> Result<(), String> {
+    let ObjectContents { name, transform, intersectable, material } = contents;
+    transform.check_at(&format!("{}.transform", path))?;
+    let intersectable = intersectable.build_with(&self.prototypes, &format!("{}.intersectable", path))?;
+    self.add_object(Object { id: 0, name, parent, transform, intersectable, material });
+    Ok(())
+  }
+
+  fn add_group_contents(&mut self, parent: Option<usize>, contents: GroupContents, path: &str) -> Result<(), String> {
+    let GroupContents { name, transform, objects, groups } = contents;
+    transform.check_at(&format!("{}.transform", path))?;
+    let id = self.add_group(Group { id: 0, name, parent, transform });
+    for (index, object) in objects.into_iter().enumerate() {
+      self.add_object_contents(Some(id), object, &format!("{}.objects[{}]", path, index))?;
+    }
+    for (index, group) in groups.into_iter().enumerate() {
+      self.add_group_contents(Some(id), group, &format!("{}.groups[{}]", path, index))?;
+    }
+    Ok(())
+  }
+
+  fn group_views(&self, parent: Option<usize>) -> Vec<GroupView<'_>> {
+    self.groups.iter().filter(|group| group.parent == parent).map(|group| GroupView {
+      name: group.name.as_deref(),
+      transform: &group.transform,
+      objects: self.objects.iter().filter(|object| object.parent == Some(group.id)).collect(),
+      groups: self.group_views(Some(group.id)),
+    }).collect()
+  }
+
+  /// Add an object under a new id, which gets returned
+  pub fn add_object(&mut self, object: Object) -> usize {
+    let id = self.next_id;
+    self.next_id += 1;
+    self.bvh = None;
+    self.objects.push(Object { id, ..object });
+    id
+  }
+
+  fn index_of(&self, id: usize) -> Option<usize> {
+    self.objects.iter().position(|object| object.id == id)
+  }
+
+  pub fn object(&self, id: usize) -> Option<&Object> {
+    self.index_of(id).map(|index| &self.objects[index])
+  }
+
+  /// First object with the given name
+  pub fn find_object(&self, name: &str) -> Option<&Object> {
+    self.objects.iter().find(|object| object.name.as_deref() == Some(name))
+  }
+
+  pub fn remove_object(&mut self, id: usize) -> Option<Object> {
+    let index = self.index_of(id)?;
+    self.bvh = None;
+    Some(self.objects.remove(index))
+  }
+
+  /// Replace the object with the given id, which the new object keeps
+  pub fn update_object(&mut self, id: usize, object: Object) -> bool {
+    match self.index_of(id) {
+      Some(index) => {
+        self.objects[index] = Object { id, ..object };
+        self.bvh = None;
+        true
+      },
+      None => false
+    }
+  }
+
+  /// Add a prototype for instances to share. Fails if its name is taken,
+  /// as instances refer to their prototype by name in scene files.
+  pub fn add_prototype(&mut self, prototype: Prototype) -> Result<Arc<Prototype>, String> {
+    if self.prototype(&prototype.name).is_some() {
+      return Err(format!("there already is a prototype named \"{}\"", prototype.name));
+    }
+    let prototype = Arc::new(prototype);
+    self.prototypes.push(Arc::clone(&prototype));
+    Ok(prototype)
   }
 
-  pub fn intersect(&self, ray: &Ray) -> Option<Intersection> {
-    self.objects.iter().fold(None, |acc, obj| {
-      Intersection::min(acc, obj.intersect(&ray))
+  pub fn prototype(&self, name: &str) -> Option<&Arc<Prototype>> {
+    self.prototypes.iter().find(|prototype| prototype.name == name)
+  }
+
+  /// Add a group under a new id, taken from the same sequence as object
+  /// ids, which gets returned
+  pub fn add_group(&mut self, group: Group) -> usize {
+    let id = self.next_id;
+    self.next_id += 1;
+    self.bvh = None;
+    self.groups.push(Group { id, ..group });
+    id
+  }
+
+  pub fn group(&self, id: usize) -> Option<&Group> {
+    self.groups.iter().find(|group| group.id == id)
+  }
+
+  /// First group with the given name
+  pub fn find_group(&self, name: &str) -> Option<&Group> {
+    self.groups.iter().find(|group| group.name.as_deref() == Some(name))
+  }
+
+  /// Remove a group along with every object and group below it
+  pub fn remove_group(&mut self, id: usize) -> Option<Group> {
+    let index = self.groups.iter().position(|group| group.id == id)?;
+    let group = self.groups.remove(index);
+    let children: Vec<usize> = self.groups.iter().filter(|child| child.parent == Some(id)).map(|child| child.id).collect();
+    for child in children {
+      self.remove_group(child);
+    }
+    self.objects.retain(|object| object.parent != Some(id));
+    self.bvh = None;
+    Some(group)
+  }
+
+  /// Replace the group with the given id, which the new group keeps. Every
+  /// object below it moves along when the BVH gets rebuilt.
+  pub fn update_group(&mut self, id: usize, group: Group) -> bool {
+    match self.groups.iter().position(|group| group.id == id) {
+      Some(index) => {
+        self.groups[index] = Group { id, ..group };
+        self.bvh = None;
+        true
+      },
+      None => false
+    }
+  }
+
+  /// Whether `parent` may hold the object or group `id`: it must be a
+  /// group of the scene, and for a group neither the group itself nor one
+  /// below it. `None`, the root of the scene, is always valid.
+  pub fn is_valid_parent(&self, id: Option<usize>, parent: Option<usize>) -> bool {
+    let mut ancestor = parent;
+    while let Some(ancestor_id) = ancestor {
+      if Some(ancestor_id) == id {
+        return false;
+      }
+      match self.group(ancestor_id) {
+        Some(group) => ancestor = group.parent,
+        None => return false
+      }
+    }
+    true
+  }
+
+  /// Matrix taking the space of the group `parent` to the world, composing
+  /// the transforms of the group and every group above it
+  pub fn group_matrix(&self, parent: Option<usize>) -> Matrix4 {
+    let mut matrix = Matrix4::identity();
+    let mut parent = parent.and_then(|id| self.group(id));
+
+    // Bounded by the number of groups in case of a cycle
+    for _ in 0..self.groups.len() {
+      let group = match parent {
+        Some(group) => group,
+        None => break
+      };
+      let transform: Matrix4 = group.transform.into();
+      matrix = transform * matrix;
+      parent = group.parent.and_then(|id| self.group(id));
+    }
+    matrix
+  }
+
+  /// Matrix taking the space of an object to the world
+  pub fn world_matrix(&self, object: &Object) -> Matrix4 {
+    let transform: Matrix4 = object.transform.into();
+    self.group_matrix(object.parent) * transform
+  }
+
+  /// Build the BVH if the objects or groups changed since it was last
+  /// built, recomputing the world placement of every object. Must be called
+  /// before `intersect`.
+  pub fn build_bvh(&mut self) {
+    if self.bvh.is_some() {
+      return;
+    }
+    self.world = self.objects.iter().map(|object| Placement::new(self.world_matrix(object))).collect();
+    let mut bounded = vec![];
+    self.unbounded.clear();
+    for (index, object) in self.objects.iter().enumerate() {
+      let bb = match self.world[index] {
+        Some(ref world) => object.world_bounding_box(world.matrix),
+        None => continue
+      };
+      if bb.is_finite() {
+        bounded.push((index, bb));
+      } else {
+        self.unbounded.push(index);
+      }
+    }
+    self.bvh = Some(Bvh::new(bounded));
+  }
+
+  pub fn add_light(&mut self, light: Box<dyn Light + Send>) -> usize {
+    self.lights.push(light);
+    self.lights.len() - 1
+  }
+
+  pub fn remove_light(&mut self, index: usize) -> Option<Box<dyn Light + Send>> {
+    if index < self.lights.len() {
+      Some(self.lights.remove(index))
+    } else {
+      None
+    }
+  }
+
+  /// Closest hit along `ray`, telling which object was hit. Panics if the
+  /// BVH was not built since the objects or groups last changed.
+  pub fn intersect(&self, ray: &Ray) -> Option<Intersection<'_>> {
+    let bvh = self.bvh.as_ref().expect("Scene::build_bvh not called before intersecting");
+    let closest = bvh.intersect(ray, |index| self.intersect_object(index, ray));
+    self.unbounded.iter().fold(closest, |acc, &index| {
+      Intersection::min(acc, self.intersect_object(index, ray))
+    })
+  }
+
+  fn intersect_object(&self, index: usize, ray: &Ray) -> Option<Intersection<'_>> {
+    let object = &self.objects[index];
+    let world = self.world[index].as_ref()?;
+    object.intersect(ray, world).map(|itsct| Intersection { object: Some(object.id), ..itsct })
+  }
+
+  /// Shadow ray test: whether anything blocks `ray` before `distance`
+  pub fn occluded(&self, ray: &Ray, distance: f32) -> bool {
+    match self.intersect(ray) {
+      Some(itsct) => itsct.t < distance - RAY_EPSILON,
+      None => false
+    }
+  }
+
+  /// Closest emitting light surface hit by `ray`, as the index of the
+  /// light, its distance and the radiance it emits towards the ray origin
+  pub fn intersect_lights(&self, ray: &Ray) -> Option<(usize, f32, Spectrum)> {
+    self.lights.iter().enumerate().fold(None, |acc, (index, light)| {
+      match (acc, light.intersect(ray)) {
+        (Some((i, t1, l1)), Some((t2, l2))) => if t1 < t2 { Some((i, t1, l1)) } else { Some((index, t2, l2)) },
+        (acc, Some((t, l))) => acc.or(Some((index, t, l))),
+        (acc, None) => acc
+      }
+    })
+  }
+
+  /// Light reflected towards `wo` by the hit surface, gathering a single
+  /// sample (using the same `u`) of every light in the scene
+  pub fn direct_lighting(&self, itsct: &Intersection, wo: Vector3, u: (f32, f32)) -> Spectrum {
+    let (position, normal) = (itsct.position, itsct.normal);
+    let material = match itsct.material {
+      Some(material) => material,
+      None => return Vector3::zero()
+    };
+    self.lights.iter().fold(Vector3::zero(), |acc, light| {
+      match light.sample_li(position, u) {
+        Some(ls) => {
+          let f = material.eval(wo, ls.wi, normal);
+          let cos_theta = ls.wi.dot(&normal).abs();
+          if f.max_component() <= 0.0 || cos_theta <= 0.0 || ls.pdf <= 0.0 {
+            return acc;
+          }
+          let shadow_ray = Ray::spawn(position, normal, ls.wi);
+          if self.occluded(&shadow_ray, ls.distance) {
+            acc
+          } else {
+            acc + f * ls.radiance * (cos_theta / ls.pdf)
+          }
+        },
+        None => acc
+      }
     })
   }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::f32::consts::PI;
+  use ::math::Quaternion;
+  use ::intersectable::Sphere;
+
+  fn group(parent: Option<usize>, transform: Transform) -> Group {
+    Group { id: 0, name: None, parent, transform }
+  }
+
+  fn sphere(parent: Option<usize>, transform: Transform) -> Object {
+    Object {
+      id: 0,
+      name: None,
+      parent,
+      transform,
+      intersectable: Box::new(Sphere::new(0.5)),
+      material: default_material(),
+    }
+  }
+
+  fn hit(scene: &mut Scene, origin: Vector3) -> Option<(usize, f32)> {
+    scene.build_bvh();
+    scene.intersect(&Ray::new(origin, vec3!(0.0, 0.0, -1.0))).map(|itsct| (itsct.object.unwrap(), itsct.t))
+  }
+
+  #[test]
+  fn group_transforms_compose_onto_children() {
+    let mut scene = Scene::new();
+    let outer = scene.add_group(group(None, Transform { position: vec3!(2.0, 0.0, 0.0), scale: vec3!(2.0), ..Transform::identity() }));
+    let half_turn = Quaternion::axis_angle(vec3!(0.0, 1.0, 0.0), PI);
+    let inner = scene.add_group(group(Some(outer), Transform { position: vec3!(0.0, 1.0, 0.0), rotation: half_turn, ..Transform::identity() }));
+    let id = scene.add_object(sphere(Some(inner), Transform { position: vec3!(1.0, 0.0, 0.0), ..Transform::identity() }));
+
+    // The half turn sends the object to x = -1 within the outer group,
+    // which doubles and shifts it: a sphere of radius 1 around (0, 2, 0)
+    let (object, t) = hit(&mut scene, vec3!(0.0, 2.0, 10.0)).unwrap();
+    assert_eq!(object, id);
+    assert!((t - 9.0).abs() < 1e-4, "{}", t);
+    assert!(hit(&mut scene, vec3!(0.0, 3.5, 10.0)).is_none());
+
+    // Moving a group moves everything below it
+    scene.update_group(outer, group(None, Transform { position: vec3!(-2.0, 0.0, 0.0), scale: vec3!(2.0), ..Transform::identity() }));
+    assert!(hit(&mut scene, vec3!(0.0, 2.0, 10.0)).is_none());
+    let (_, t) = hit(&mut scene, vec3!(-4.0, 2.0, 10.0)).unwrap();
+    assert!((t - 9.0).abs() < 1e-4, "{}", t);
+
+    // A group flattened to nothing hides its objects instead of failing
+    scene.update_group(inner, group(Some(outer), Transform { scale: vec3!(1.0, 0.0, 1.0), ..Transform::identity() }));
+    assert!(hit(&mut scene, vec3!(-2.0, 0.0, 10.0)).is_none());
+  }
+
+  #[test]
+  fn objects_outside_of_groups_stay_in_place() {
+    let mut scene = Scene::new();
+    scene.add_group(group(None, Transform { position: vec3!(5.0, 0.0, 0.0), ..Transform::identity() }));
+    let id = scene.add_object(sphere(None, Transform::identity()));
+    assert_eq!(hit(&mut scene, vec3!(0.0, 0.0, 10.0)).map(|(object, _)| object), Some(id));
+  }
+}
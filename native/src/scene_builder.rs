@@ -0,0 +1,245 @@
+use neon::prelude::*;
+use serde_json;
+
+use math::Vector3;
+use scene::Scene;
+use mesh::TriangleMesh;
+use material::{Material, Lambertian};
+use convert::{object_from_js, group_from_js, prototype_from_js, light_from_js, mesh_from_js, material_from_js, json_to_js};
+use validate::count;
+
+type MaterialBox = Box<dyn Material + Send>;
+
+/// Throw a `RangeError` unless `parent` may hold the object or group `id`
+/// of the scene, `None` standing for a node yet to be added
+fn check_parent<'a, C: Context<'a>>(cx: &mut C, scene: Handle<'a, JsScene>, id: Option<usize>, parent: Option<usize>) -> NeonResult<()> {
+  if cx.borrow(&scene, |scene| scene.is_valid_parent(id, parent)) {
+    return Ok(());
+  }
+  match (parent, id) {
+    (Some(parent), Some(id)) => cx.throw_range_error(format!("Group {} cannot be the parent of {}", parent, id)),
+    (Some(parent), None) => cx.throw_range_error(format!("No group with id {}", parent)),
+    (None, _) => Ok(())
+  }
+}
+
+declare_types! {
+  pub class JsScene for Scene {
+    init(_cx) {
+      Ok(Scene::new())
+    }
+
+    // Adds an `{ name, parent, transform, intersectable, material }`
+    // descriptor to the scene and returns the id of the newly created
+    // object. Ids stay the same as other objects come and go.
+    method addObject(mut cx) {
+      let descriptor = cx.argument::<JsObject>(0)?;
+      let mut this = cx.this();
+      let prototypes = cx.borrow(&this, |scene| scene.prototypes.clone());
+      let object = object_from_js(&mut cx, descriptor, &prototypes)?;
+      check_parent(&mut cx, this, None, object.parent)?;
+      let id = cx.borrow_mut(&mut this, |mut scene| scene.add_object(object));
+      Ok(cx.number(id as f64).upcast())
+    }
+
+    // Removes the object with the given id. Returns whether there was one.
+    method removeObject(mut cx) {
+      let id = cx.argument::<JsValue>(0)?;
+      let id = count(&mut cx, id, "id")?;
+      let mut this = cx.this();
+      let removed = cx.borrow_mut(&mut this, |mut scene| scene.remove_object(id).is_some());
+      Ok(cx.boolean(removed).upcast())
+    }
+
+    // Replaces the object with the given id by a new descriptor, keeping
+    // the id. Returns whether there was an object to replace.
+    method updateObject(mut cx) {
+      let id = cx.argument::<JsValue>(0)?;
+      let id = count(&mut cx, id, "id")?;
+      let descriptor = cx.argument::<JsObject>(1)?;
+      let mut this = cx.this();
+      let prototypes = cx.borrow(&this, |scene| scene.prototypes.clone());
+      let object = object_from_js(&mut cx, descriptor, &prototypes)?;
+      check_parent(&mut cx, this, None, object.parent)?;
+      let updated = cx.borrow_mut(&mut this, |mut scene| scene.update_object(id, object));
+      Ok(cx.boolean(updated).upcast())
+    }
+
+    // Descriptor of the object with the given id, as in scene files plus
+    // its `id` and `parent`, or null if there is none.
+    method getObject(mut cx) {
+      let id = cx.argument::<JsValue>(0)?;
+      let id = count(&mut cx, id, "id")?;
+      let this = cx.this();
+      let json = cx.borrow(&this, |scene| scene.object(id).map(|object| (serde_json::to_value(object), object.parent)));
+      match json {
+        Some((Ok(mut json), parent)) => {
+          json["id"] = id.into();
+          json["parent"] = parent.into();
+          json_to_js(&mut cx, &json)
+        },
+        Some((Err(err), _)) => cx.throw_error(err.to_string()),
+        None => Ok(cx.null().upcast())
+      }
+    }
+
+    // Id of the first object with the given name, or null if there is none.
+    method findObject(mut cx) {
+      let name = cx.argument::<JsString>(0)?.value();
+      let this = cx.this();
+      let id = cx.borrow(&this, |scene| scene.find_object(&name).map(|object| object.id));
+      match id {
+        Some(id) => Ok(cx.number(id as f64).upcast()),
+        None => Ok(cx.null().upcast())
+      }
+    }
+
+    // Ids of every object, in the order they were added.
+    method objectIds(mut cx) {
+      let this = cx.this();
+      let ids: Vec<usize> = cx.borrow(&this, |scene| scene.objects.iter().map(|object| object.id).collect());
+      let arr = JsArray::new(&mut cx, ids.len() as u32);
+      for (i, id) in ids.into_iter().enumerate() {
+        let id = cx.number(id as f64);
+        arr.set(&mut cx, i as u32, id)?;
+      }
+      Ok(arr.upcast())
+    }
+
+    // Adds a `{ name, parts }` prototype, each part being a `{ transform,
+    // intersectable, material }` object. Objects then take copies of it as
+    // `{ type: "instance", prototype: name, transform }` intersectables,
+    // which share its geometry instead of copying it. Throws if there
+    // already is a prototype with the same name.
+    method addPrototype(mut cx) {
+      let descriptor = cx.argument::<JsObject>(0)?;
+      let mut this = cx.this();
+      let prototypes = cx.borrow(&this, |scene| scene.prototypes.clone());
+      let prototype = prototype_from_js(&mut cx, descriptor, &prototypes)?;
+      let added = cx.borrow_mut(&mut this, |mut scene| scene.add_prototype(prototype).map(|_| ()));
+      added.or_else(|err| cx.throw_error(err))?;
+      Ok(cx.undefined().upcast())
+    }
+
+    // Adds a `{ name, parent, transform }` group to the scene and returns
+    // its id, drawn from the same sequence as object ids. Objects and groups
+    // naming it as their `parent` move along with it.
+    method addGroup(mut cx) {
+      let descriptor = cx.argument::<JsObject>(0)?;
+      let group = group_from_js(&mut cx, descriptor)?;
+      let mut this = cx.this();
+      check_parent(&mut cx, this, None, group.parent)?;
+      let id = cx.borrow_mut(&mut this, |mut scene| scene.add_group(group));
+      Ok(cx.number(id as f64).upcast())
+    }
+
+    // Removes the group with the given id along with everything below it.
+    // Returns whether there was one.
+    method removeGroup(mut cx) {
+      let id = cx.argument::<JsValue>(0)?;
+      let id = count(&mut cx, id, "id")?;
+      let mut this = cx.this();
+      let removed = cx.borrow_mut(&mut this, |mut scene| scene.remove_group(id).is_some());
+      Ok(cx.boolean(removed).upcast())
+    }
+
+    // Replaces the group with the given id by a new descriptor, keeping the
+    // id and everything below it, e.g. to move an articulated model as a
+    // whole. Returns whether there was a group to replace.
+    method updateGroup(mut cx) {
+      let id = cx.argument::<JsValue>(0)?;
+      let id = count(&mut cx, id, "id")?;
+      let descriptor = cx.argument::<JsObject>(1)?;
+      let group = group_from_js(&mut cx, descriptor)?;
+      let mut this = cx.this();
+      check_parent(&mut cx, this, Some(id), group.parent)?;
+      let updated = cx.borrow_mut(&mut this, |mut scene| scene.update_group(id, group));
+      Ok(cx.boolean(updated).upcast())
+    }
+
+    // `{ id, name, parent, transform }` of the group with the given id, or
+    // null if there is none.
+    method getGroup(mut cx) {
+      let id = cx.argument::<JsValue>(0)?;
+      let id = count(&mut cx, id, "id")?;
+      let this = cx.this();
+      let json = cx.borrow(&this, |scene| scene.group(id).map(serde_json::to_value));
+      match json {
+        Some(Ok(json)) => json_to_js(&mut cx, &json),
+        Some(Err(err)) => cx.throw_error(err.to_string()),
+        None => Ok(cx.null().upcast())
+      }
+    }
+
+    // Id of the first group with the given name, or null if there is none.
+    method findGroup(mut cx) {
+      let name = cx.argument::<JsString>(0)?.value();
+      let this = cx.this();
+      let id = cx.borrow(&this, |scene| scene.find_group(&name).map(|group| group.id));
+      match id {
+        Some(id) => Ok(cx.number(id as f64).upcast()),
+        None => Ok(cx.null().upcast())
+      }
+    }
+
+    // Adds a light descriptor to the scene and returns the index of the
+    // newly created light.
+    method addLight(mut cx) {
+      let descriptor = cx.argument::<JsObject>(0)?;
+      let light = light_from_js(&mut cx, descriptor)?;
+      let mut this = cx.this();
+      let index = cx.borrow_mut(&mut this, |mut scene| scene.add_light(light));
+      Ok(cx.number(index as f64).upcast())
+    }
+
+    // Removes the light at the given index. Returns whether there was one.
+    method removeLight(mut cx) {
+      let index = cx.argument::<JsValue>(0)?;
+      let index = count(&mut cx, index, "index")?;
+      let mut this = cx.this();
+      let removed = cx.borrow_mut(&mut this, |mut scene| scene.remove_light(index).is_some());
+      Ok(cx.boolean(removed).upcast())
+    }
+
+    // Plain `{ prototypes, objects, groups, lights }` object in the scene
+    // file format, so that `JSON.stringify(scene)` gives the scene part of a
+    // scene file
+    method toJSON(mut cx) {
+      let this = cx.this();
+      let json = cx.borrow(&this, |scene| serde_json::to_value(&*scene));
+      match json {
+        Ok(json) => json_to_js(&mut cx, &json),
+        Err(err) => cx.throw_error(err.to_string())
+      }
+    }
+  }
+
+  // Native triangle mesh, either built from `{ positions, normals, uvs,
+  // indices }` flat arrays or returned by `loadObj`. Can be used in place of
+  // an intersectable descriptor, sharing the mesh data between objects.
+  pub class JsMesh for TriangleMesh {
+    init(mut cx) {
+      match cx.argument_opt(0).map(|arg| arg.downcast::<JsObject>()) {
+        Some(Ok(descriptor)) => mesh_from_js(&mut cx, descriptor, "mesh"),
+        _ => Ok(TriangleMesh::new(vec![], vec![], vec![], vec![]))
+      }
+    }
+
+    method triangleCount(mut cx) {
+      let this = cx.this();
+      let count = cx.borrow(&this, |mesh| mesh.triangles.len());
+      Ok(cx.number(count as f64).upcast())
+    }
+  }
+
+  // Native material built from a material descriptor, or loaded from an MTL
+  // library. Can be used in place of a material descriptor.
+  pub class JsMaterial for MaterialBox {
+    init(mut cx) {
+      match cx.argument_opt(0).map(|arg| arg.downcast::<JsObject>()) {
+        Some(Ok(descriptor)) => material_from_js(&mut cx, descriptor, "material"),
+        _ => Ok(Box::new(Lambertian::new(vec3!(0.8))))
+      }
+    }
+  }
+}
@@ -0,0 +1,133 @@
+use std::error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde_json;
+
+use ::scene::Scene;
+use ::camera::CameraDescriptor;
+use ::renderer::RenderSettings;
+
+/// Contents of a JSON scene file, see `docs/scene-format.md`
+#[derive(Clone, Serialize, Deserialize)]
+pub struct SceneFile {
+  #[serde(default)]
+  pub camera: CameraDescriptor,
+
+  #[serde(default)]
+  pub render: RenderSettings,
+
+  /// `objects` and `lights` sit at the top level of the file
+  #[serde(flatten)]
+  pub scene: Scene,
+}
+
+#[derive(Debug)]
+pub enum SceneFileError {
+  Io(PathBuf, io::Error),
+  Json(PathBuf, serde_json::Error),
+}
+
+impl fmt::Display for SceneFileError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      SceneFileError::Io(path, err) => write!(f, "{}: {}", path.display(), err),
+      SceneFileError::Json(path, err) => write!(f, "{}: {}", path.display(), err),
+    }
+  }
+}
+
+impl error::Error for SceneFileError {}
+
+pub fn load_scene_file(path: &Path) -> Result<SceneFile, SceneFileError> {
+  let text = fs::read_to_string(path).map_err(|err| SceneFileError::Io(path.to_path_buf(), err))?;
+  serde_json::from_str(&text).map_err(|err| SceneFileError::Json(path.to_path_buf(), err))
+}
+
+pub fn save_scene_file(path: &Path, file: &SceneFile) -> Result<(), SceneFileError> {
+  let text = serde_json::to_string_pretty(file).map_err(|err| SceneFileError::Json(path.to_path_buf(), err))?;
+  fs::write(path, text).map_err(|err| SceneFileError::Io(path.to_path_buf(), err))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::env;
+  use std::process;
+  use serde_json::Value;
+
+  const SCENE: &str = r#"{
+    "camera": { "target": [0, 0.5, 0], "azimuth": 0.6, "distance": 4 },
+    "render": { "width": 32, "height": 24, "integrator": "path", "spp": 4, "filter": { "type": "gaussian" } },
+    "prototypes": [
+      { "name": "pebble", "parts": [{ "intersectable": { "type": "sphere", "radius": 0.2 } }] }
+    ],
+    "objects": [
+      { "name": "floor", "intersectable": { "type": "plane" }, "material": { "type": "diffuse", "albedo": [0.7, 0.7, 0.7] } },
+      { "transform": { "position": [1, 0, 0] }, "intersectable": { "type": "instance", "prototype": "pebble" } }
+    ],
+    "groups": [
+      {
+        "name": "table",
+        "transform": { "position": [0, 1, 0], "scale": [2, 1, 2] },
+        "objects": [{ "name": "cup", "intersectable": { "type": "cube", "size": [0.2, 0.3, 0.2] } }],
+        "groups": [{ "objects": [{ "intersectable": { "type": "sphere", "radius": 0.1 } }] }]
+      }
+    ],
+    "lights": [{ "type": "point", "position": [0, 3, 0], "intensity": [5, 5, 5] }]
+  }"#;
+
+  /// Parse `text` as a scene file through a file on disk
+  fn load(test: &str, text: &str) -> Result<SceneFile, SceneFileError> {
+    let path = env::temp_dir().join(format!("photon-scene-{}-{}.json", test, process::id()));
+    fs::write(&path, text).unwrap();
+    let result = load_scene_file(&path);
+    fs::remove_file(&path).unwrap();
+    result
+  }
+
+  #[test]
+  fn saved_scenes_load_back_the_same() {
+    let file = load("original", SCENE).unwrap();
+    let scene = &file.scene;
+    assert_eq!((scene.objects.len(), scene.groups.len(), scene.prototypes.len(), scene.lights.len()), (4, 2, 1, 1));
+    assert_eq!(file.render.spp, Some(4));
+    let cup = scene.find_object("cup").unwrap();
+    assert_eq!(cup.parent, scene.find_group("table").map(|group| group.id));
+
+    let path = env::temp_dir().join(format!("photon-scene-saved-{}.json", process::id()));
+    save_scene_file(&path, &file).unwrap();
+    let saved = fs::read_to_string(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+    let reloaded = load("reloaded", &saved).unwrap();
+
+    // Ids get handed out in the same order again, and saving the loaded
+    // scene gives back the same file
+    let ids = |file: &SceneFile| file.scene.objects.iter().map(|object| (object.id, object.name.clone(), object.parent)).collect::<Vec<_>>();
+    assert_eq!(ids(&reloaded), ids(&file));
+    let json = |file: &SceneFile| serde_json::to_value(file).unwrap();
+    assert_eq!(json(&reloaded), json(&file));
+    assert_eq!(json(&file)["groups"][0]["objects"][0]["name"], Value::from("cup"));
+  }
+
+  #[test]
+  fn misspelled_fields_are_errors() {
+    for &(from, to) in &[("\"transform\": { \"position\": [1", "\"tranform\": { \"position\": [1"), ("\"intensity\"", "\"intensty\""), ("\"spp\"", "\"sp\"")] {
+      match load("typo", &SCENE.replacen(from, to, 1)) {
+        Err(SceneFileError::Json(_, err)) => assert!(err.to_string().contains("unknown field"), "{}", err),
+        _ => panic!("{} was accepted", to),
+      }
+    }
+  }
+
+  #[test]
+  fn flattening_transforms_are_errors() {
+    let text = SCENE.replacen("\"scale\": [2, 1, 2]", "\"scale\": [2, 0, 2]", 1);
+    match load("flat", &text) {
+      Err(SceneFileError::Json(_, err)) => assert!(err.to_string().contains("groups[0].transform.scale"), "{}", err),
+      _ => panic!("zero scale was accepted"),
+    }
+  }
+}
@@ -0,0 +1,161 @@
+use ::math::{Color, Vector3, Spectrum};
+use ::sampler::{Sampler, RandomSampler};
+
+/// Curve compressing linear radiance into the displayable `[0, 1]` range
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ToneMapOperator {
+  /// Plain clipping
+  Linear,
+
+  /// `L / (1 + L)` on the luminance, keeping the hue
+  Reinhard,
+
+  /// Narkowicz's fit of the ACES filmic curve, per channel
+  #[serde(alias = "filmic")]
+  Aces,
+}
+
+impl ToneMapOperator {
+  /// Parse `"linear"`, `"reinhard"` or `"aces"`, also known as `"filmic"`
+  pub fn from_name(name: &str) -> Option<Self> {
+    match name {
+      "linear" => Some(ToneMapOperator::Linear),
+      "reinhard" => Some(ToneMapOperator::Reinhard),
+      "aces" | "filmic" => Some(ToneMapOperator::Aces),
+      _ => None,
+    }
+  }
+}
+
+/// Turns film radiance into 8 bit display colors
+#[derive(Clone, Copy, Debug)]
+pub struct ToneMapper {
+  /// Exposure adjustment in stops
+  pub exposure: f32,
+  pub operator: ToneMapOperator,
+
+  /// Encode with the sRGB transfer function rather than storing linear values
+  pub srgb: bool,
+
+  /// Add a little noise before quantizing to break up banding
+  pub dither: bool,
+}
+
+impl Default for ToneMapper {
+  fn default() -> Self {
+    Self { exposure: 0.0, operator: ToneMapOperator::Linear, srgb: true, dither: true }
+  }
+}
+
+impl ToneMapper {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Display color of the radiance of pixel `(x, y)`, whose position only
+  /// seeds the dithering
+  pub fn map(&self, radiance: Spectrum, x: usize, y: usize) -> Color {
+    let c = (radiance * 2f32.powf(self.exposure)).max(&Vector3::zero());
+    let c = match self.operator {
+      ToneMapOperator::Linear => c,
+      ToneMapOperator::Reinhard => {
+        let luminance = 0.2126 * c.x + 0.7152 * c.y + 0.0722 * c.z;
+        if luminance > 0.0 { c * (1.0 / (1.0 + luminance)) } else { c }
+      },
+      ToneMapOperator::Aces => vec3!(aces(c.x), aces(c.y), aces(c.z)),
+    };
+    let c = if self.srgb { vec3!(srgb_oetf(c.x), srgb_oetf(c.y), srgb_oetf(c.z)) } else { c };
+
+    // Triangular noise of one quantization step, independent per channel
+    let noise = if self.dither {
+      let mut sampler = RandomSampler::for_pixel(x, y, 0);
+      let mut triangular = || (sampler.next_1d() - sampler.next_1d()) / 255.0;
+      vec3!(triangular(), triangular(), triangular())
+    } else {
+      Vector3::zero()
+    };
+    Color { r: quantize(c.x + noise.x), g: quantize(c.y + noise.y), b: quantize(c.z + noise.z), a: 255 }
+  }
+}
+
+fn aces(x: f32) -> f32 {
+  let x = x * 0.6;
+  (x * (2.51 * x + 0.03) / (x * (2.43 * x + 0.59) + 0.14)).clamp(0.0, 1.0)
+}
+
+/// The sRGB opto-electronic transfer function
+pub fn srgb_oetf(c: f32) -> f32 {
+  if c <= 0.003_130_8 {
+    12.92 * c
+  } else {
+    1.055 * c.powf(1.0 / 2.4) - 0.055
+  }
+}
+
+fn quantize(c: f32) -> u8 {
+  (c * 255.0 + 0.5).clamp(0.0, 255.0) as u8
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn mapper(operator: ToneMapOperator, srgb: bool) -> ToneMapper {
+    ToneMapper { exposure: 0.0, operator, srgb, dither: false }
+  }
+
+  fn rgb(color: Color) -> (u8, u8, u8) {
+    (color.r, color.g, color.b)
+  }
+
+  #[test]
+  fn srgb_encoding_matches_reference_values() {
+    assert_eq!(srgb_oetf(0.0), 0.0);
+    assert!((srgb_oetf(1.0) - 1.0).abs() < 1e-6);
+    assert!((srgb_oetf(0.002) - 0.02584).abs() < 1e-5);
+    assert!((srgb_oetf(0.18) - 0.46135).abs() < 1e-4);
+
+    // Mid grey lands on the well known 8 bit value
+    assert_eq!(rgb(mapper(ToneMapOperator::Linear, true).map(vec3!(0.18), 0, 0)), (118, 118, 118));
+    assert_eq!(rgb(mapper(ToneMapOperator::Linear, false).map(vec3!(0.18), 0, 0)), (46, 46, 46));
+  }
+
+  #[test]
+  fn linear_clips_and_exposure_scales() {
+    let linear = mapper(ToneMapOperator::Linear, false);
+    assert_eq!(rgb(linear.map(vec3!(-1.0, 0.5, 4.0), 0, 0)), (0, 128, 255));
+    let brighter = ToneMapper { exposure: 1.0, ..linear };
+    assert_eq!(rgb(brighter.map(vec3!(0.25), 0, 0)), (128, 128, 128));
+  }
+
+  #[test]
+  fn reinhard_keeps_the_hue() {
+    let reinhard = mapper(ToneMapOperator::Reinhard, false);
+    assert_eq!(rgb(reinhard.map(vec3!(1.0), 0, 0)), (128, 128, 128));
+    let (r, g, b) = rgb(reinhard.map(vec3!(0.8, 0.4, 0.2), 0, 0));
+    assert!(r > g && g > b && b > 0 && r < 255);
+    assert!((r as f32 / g as f32 - 2.0).abs() < 0.05);
+  }
+
+  #[test]
+  fn aces_saturates_smoothly() {
+    let aces = mapper(ToneMapOperator::Aces, false);
+    assert_eq!(rgb(aces.map(Vector3::zero(), 0, 0)), (0, 0, 0));
+    assert_eq!(rgb(aces.map(vec3!(100.0), 0, 0)), (255, 255, 255));
+    let values: Vec<u8> = [0.1, 0.5, 1.0, 2.0, 4.0].iter().map(|&l| aces.map(vec3!(l), 0, 0).r).collect();
+    assert!(values.windows(2).all(|pair| pair[0] < pair[1]), "{:?}", values);
+    assert_eq!(ToneMapOperator::from_name("filmic"), Some(ToneMapOperator::Aces));
+  }
+
+  #[test]
+  fn dithering_stays_within_one_step() {
+    let dithered = ToneMapper { dither: true, ..mapper(ToneMapOperator::Linear, false) };
+    for y in 0..16 {
+      for x in 0..16 {
+        let r = dithered.map(vec3!(0.5), x, y).r;
+        assert!(r == 127 || r == 128 || r == 129, "{} at ({}, {})", r, x, y);
+      }
+    }
+  }
+}
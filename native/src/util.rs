@@ -1,4 +1,28 @@
-use ::math::{Color, Vector3, Vector4, Quaternion, Matrix4};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+
+use ::math::{Color, Vector3, Quaternion, Matrix4};
+use ::material::Material;
+
+pub const RAY_EPSILON: f32 = 1e-4;
+
+/// Matrix placing a shape, along with the inverse taking rays into the
+/// space of the shape and the inverse transpose taking its normals out.
+/// Both get computed once rather than for every ray.
+#[derive(Clone, Copy)]
+pub struct Placement {
+  pub matrix: Matrix4,
+  pub inverse: Matrix4,
+  pub normal: Matrix4,
+}
+
+impl Placement {
+  /// `None` if the matrix flattens the shape, which then cannot be hit
+  pub fn new(matrix: Matrix4) -> Option<Self> {
+    matrix.inverse().map(|inverse| Self { matrix, inverse, normal: inverse.transpose() })
+  }
+}
 
 pub struct Ray {
   pub origin: Vector3,
@@ -10,8 +34,15 @@ impl Ray {
     Ray { origin, direction }
   }
 
+  /// Ray leaving a surface at `position`, nudged off the surface to avoid
+  /// hitting it again due to floating point error
+  pub fn spawn(position: Vector3, normal: Vector3, direction: Vector3) -> Ray {
+    let offset = if direction.dot(&normal) > 0.0 { normal * RAY_EPSILON } else { normal * -RAY_EPSILON };
+    Ray { origin: position + offset, direction }
+  }
+
   pub fn point_at(&self, t: f32) -> Vector3 {
-    self.origin.clone() + self.direction.clone() * t
+    self.origin + self.direction * t
   }
 
   pub fn transform(&self, mat: Matrix4) -> Ray {
@@ -21,37 +52,51 @@ impl Ray {
     }
   }
 
-  pub fn inverse_transform(&self, mat: Matrix4) -> Ray {
-    self.transform(mat.inverse())
+  /// The ray in the space of the shape placed by `placement`
+  pub fn inverse_transform(&self, placement: &Placement) -> Ray {
+    self.transform(placement.inverse)
   }
 }
 
+/// `normal` is the outward facing geometric normal, regardless of which
+/// side the ray came from
 #[derive(Clone, Copy)]
-pub struct Intersection {
+pub struct Intersection<'a> {
   pub position: Vector3,
   pub normal: Vector3,
   pub t: f32,
+
+  /// Texture coordinates, for shapes that carry them
+  pub uv: Option<(f32, f32)>,
+  pub material: Option<&'a dyn Material>,
+
+  /// Id of the hit object, filled in by `Scene::intersect`
+  pub object: Option<usize>,
 }
 
-impl Intersection {
+impl<'a> Intersection<'a> {
   pub fn min(lhs: Option<Self>, rhs: Option<Self>) -> Option<Self> {
     match (lhs, rhs) {
       (Some(i1), Some(i2)) => {
         if i1.t < i2.t {
-          lhs.clone()
+          lhs
         } else {
-          rhs.clone()
+          rhs
         }
       },
       _ => lhs.or(rhs)
     }
   }
 
-  pub fn transform(&self, mat: Matrix4) -> Self {
+  /// The intersection with a shape moved out of its space by `placement`
+  pub fn transform(&self, placement: &Placement) -> Self {
     Self {
-      position: self.position.transform_dehomogenous(mat),
-      normal: self.normal.transform(mat),
-      t: self.t
+      position: self.position.transform_dehomogenous(placement.matrix),
+      normal: self.normal.transform(placement.normal).normalize(),
+      t: self.t,
+      uv: self.uv,
+      material: self.material,
+      object: self.object,
     }
   }
 }
@@ -81,6 +126,7 @@ impl<'a> ImageData<'a> {
   }
 }
 
+#[derive(Clone, Copy)]
 pub struct ImageDimension {
   pub width: usize,
   pub height: usize,
@@ -159,6 +205,7 @@ impl Iterator for ImageLevelIter {
   }
 }
 
+#[derive(Clone, Copy)]
 pub struct Tile {
   pub x: usize,
   pub y: usize,
@@ -180,9 +227,11 @@ impl Iterator for TileIter {
   type Item = Tile;
 
   fn next(&mut self) -> Option<Tile> {
-    if self.y >= self.height {
-      None
-    } else {
+    while self.y < self.height {
+
+      // Past the first level, every other tile of every other row starts at
+      // a corner the coarser level has already rendered
+      let is_rendered = !self.is_init && self.in_even_row && (self.x / self.tile_size).is_multiple_of(2);
 
       let curr_x = self.x;
       let curr_y = self.y;
@@ -214,31 +263,206 @@ impl Iterator for TileIter {
       };
 
       // Do the mutation
+      if next_y != curr_y {
+        self.in_even_row = !self.in_even_row;
+      }
       self.x = next_x;
       self.y = next_y;
 
-      Some(Tile {
-        x: curr_x,
-        y: curr_y,
-        w: curr_tile_width,
-        h: curr_tile_height,
-      })
+      if !is_rendered {
+        return Some(Tile {
+          x: curr_x,
+          y: curr_y,
+          w: curr_tile_width,
+          h: curr_tile_height,
+        });
+      }
     }
+    None
   }
 }
 
-#[derive(Copy, Clone)]
+/// Every field is optional in scene files
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct Transform {
   pub position: Vector3,
   pub scale: Vector3,
   pub rotation: Quaternion,
 }
 
-impl Into<Matrix4> for Transform {
-  fn into(self) -> Matrix4 {
-    let pos_mat = Matrix4::translate_matrix(self.position);
-    let scale_mat = Matrix4::scale_matrix(self.scale);
-    let rot_mat: Matrix4 = self.rotation.into();
+impl Transform {
+  pub fn identity() -> Self {
+    Self {
+      position: Vector3::zero(),
+      scale: vec3!(1.0),
+      rotation: Quaternion::identity(),
+    }
+  }
+
+  /// Check that the matrix of the transform can be inverted. Fails with the
+  /// offending field and what it should be otherwise.
+  pub fn check(&self) -> Result<(), (&'static str, &'static str)> {
+    let Vector3 { x, y, z } = self.position;
+    if !(x.is_finite() && y.is_finite() && z.is_finite()) {
+      return Err(("position", "finite"));
+    }
+    let Vector3 { x, y, z } = self.scale;
+    if !(x.is_finite() && y.is_finite() && z.is_finite()) || x == 0.0 || y == 0.0 || z == 0.0 {
+      return Err(("scale", "finite and non-zero in every component"));
+    }
+    let length = self.rotation.dot(self.rotation);
+    if !length.is_finite() || length == 0.0 {
+      return Err(("rotation", "a finite quaternion of non-zero length"));
+    }
+    Ok(())
+  }
+
+  /// Same as `check`, naming the field as found under `path` of a scene file
+  pub fn check_at(&self, path: &str) -> Result<(), String> {
+    self.check().map_err(|(key, expectation)| format!("expected {}.{} to be {}", path, key, expectation))
+  }
+}
+
+impl Default for Transform {
+  fn default() -> Self {
+    Self::identity()
+  }
+}
+
+impl From<Transform> for Matrix4 {
+  fn from(transform: Transform) -> Matrix4 {
+    let pos_mat = Matrix4::translate_matrix(transform.position);
+    let scale_mat = Matrix4::scale_matrix(transform.scale);
+    let rot_mat: Matrix4 = transform.rotation.into();
     pos_mat * scale_mat * rot_mat
   }
-}
\ No newline at end of file
+}
+
+/// Number of worker threads used when none is configured
+pub fn default_threads() -> usize {
+  thread::available_parallelism().map(|n| n.get()).unwrap_or(1)
+}
+
+/// Apply `f` to every item on up to `threads` threads, each picking the next
+/// unclaimed item whenever it gets free. The results keep the order of
+/// `items` no matter which thread produced them.
+pub fn parallel_map<T, R, F>(threads: usize, items: &[T], f: F) -> Vec<R>
+  where T: Sync, R: Send, F: Fn(&T) -> R + Sync
+{
+  let threads = threads.min(items.len());
+  if threads <= 1 {
+    return items.iter().map(f).collect();
+  }
+  let next = AtomicUsize::new(0);
+  let mut results: Vec<Option<R>> = items.iter().map(|_| None).collect();
+  thread::scope(|scope| {
+    let workers: Vec<_> = (0..threads).map(|_| scope.spawn(|| {
+      let mut done = vec![];
+      loop {
+        let index = next.fetch_add(1, Ordering::Relaxed);
+        if index >= items.len() {
+          return done;
+        }
+        done.push((index, f(&items[index])));
+      }
+    })).collect();
+    for worker in workers {
+      for (index, result) in worker.join().expect("Render worker panicked") {
+        results[index] = Some(result);
+      }
+    }
+  });
+  results.into_iter().map(|result| result.expect("Missing result")).collect()
+}
+
+/// Chunks every worker of a `WorkerPool` gets on average out of one `map`,
+/// so that faster workers can take on more of them
+const CHUNKS_PER_WORKER: usize = 4;
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// Threads started once and fed jobs over a channel, for renders that map
+/// many batches one after the other (e.g. progressive refinement)
+pub struct WorkerPool {
+  jobs: Option<mpsc::Sender<Job>>,
+  workers: Vec<thread::JoinHandle<()>>,
+}
+
+impl WorkerPool {
+  pub fn new(threads: usize) -> Self {
+    let (jobs, jobs_rx) = mpsc::channel::<Job>();
+    let jobs_rx = Arc::new(Mutex::new(jobs_rx));
+    let workers = (0..threads.max(1)).map(|_| {
+      let jobs_rx = Arc::clone(&jobs_rx);
+      thread::spawn(move || loop {
+        // The lock is only held while waiting for the next job
+        let job = jobs_rx.lock().expect("Job lock poisoned").recv();
+        match job {
+          Ok(job) => job(),
+          Err(_) => return,
+        }
+      })
+    }).collect();
+    Self { jobs: Some(jobs), workers }
+  }
+
+  /// Same as `parallel_map` on the workers of the pool. The items are
+  /// handed out in chunks, each taken by the next worker to get free.
+  pub fn map<T, R, F>(&self, items: Vec<T>, f: F) -> Vec<R>
+    where T: Send + 'static, R: Send + 'static, F: Fn(T) -> R + Send + Sync + 'static
+  {
+    let chunk_size = (items.len() / (self.workers.len() * CHUNKS_PER_WORKER)).max(1);
+    let f = Arc::new(f);
+    let (results_tx, results_rx) = mpsc::channel();
+    let mut items = items.into_iter();
+    let mut chunks = 0;
+    loop {
+      let chunk: Vec<T> = items.by_ref().take(chunk_size).collect();
+      if chunk.is_empty() {
+        break;
+      }
+      let (f, results_tx, index) = (Arc::clone(&f), results_tx.clone(), chunks);
+      let job: Job = Box::new(move || {
+        let results: Vec<R> = chunk.into_iter().map(|item| f(item)).collect();
+        results_tx.send((index, results)).ok();
+      });
+      self.jobs.as_ref().expect("Worker pool shut down").send(job).expect("Render workers exited");
+      chunks += 1;
+    }
+    drop(results_tx);
+
+    // A worker panicking drops its sender without sending, ending the
+    // loop early
+    let mut results: Vec<Option<Vec<R>>> = (0..chunks).map(|_| None).collect();
+    for (index, chunk) in results_rx {
+      results[index] = Some(chunk);
+    }
+    results.into_iter().flat_map(|chunk| chunk.expect("Render worker panicked")).collect()
+  }
+}
+
+impl Drop for WorkerPool {
+  /// Closing the channel lets every worker finish its job and exit
+  fn drop(&mut self) {
+    self.jobs = None;
+    for worker in self.workers.drain(..) {
+      worker.join().ok();
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn worker_pool_keeps_item_order_across_maps() {
+    let pool = WorkerPool::new(4);
+    for &count in &[0, 1, 3, 100, 1000] {
+      let items: Vec<usize> = (0..count).collect();
+      let squares = pool.map(items, |item| item * item);
+      assert_eq!(squares, (0..count).map(|item| item * item).collect::<Vec<_>>());
+    }
+  }
+}
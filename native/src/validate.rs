@@ -0,0 +1,154 @@
+//! Typed reads of JS values. Optional fields left `undefined` or `null` take
+//! their defaults, while values of the wrong type throw a `TypeError` and
+//! values out of range a `RangeError`, both naming the offending field by
+//! its path from the argument, e.g. `camera.target.x`.
+
+use neon::prelude::*;
+
+use util::ImageDimension;
+
+/// Path of the field `key` of the value at `path`
+pub fn field(path: &str, key: &str) -> String {
+  format!("{}.{}", path, key)
+}
+
+/// Whether the value leaves an optional field at its default
+pub fn is_absent(value: Handle<JsValue>) -> bool {
+  value.is_a::<JsUndefined>() || value.is_a::<JsNull>()
+}
+
+fn type_name(value: Handle<JsValue>) -> &'static str {
+  if value.is_a::<JsUndefined>() {
+    "undefined"
+  } else if value.is_a::<JsNull>() {
+    "null"
+  } else if value.is_a::<JsBoolean>() {
+    "a boolean"
+  } else if value.is_a::<JsNumber>() {
+    "a number"
+  } else if value.is_a::<JsString>() {
+    "a string"
+  } else if value.is_a::<JsArray>() {
+    "an array"
+  } else if value.is_a::<JsFunction>() {
+    "a function"
+  } else {
+    "an object"
+  }
+}
+
+/// Throw a `TypeError` for the value at `path` not being `what`
+pub fn expected<'a, C: Context<'a>, T>(cx: &mut C, path: &str, what: &str, value: Handle<'a, JsValue>) -> NeonResult<T> {
+  cx.throw_type_error(format!("Expected {} to be {}, got {}", path, what, type_name(value)))
+}
+
+/// Throw a `TypeError` for any own property of `obj` outside of `known`, so
+/// that misspelled fields do not silently fall back to their defaults
+pub fn check_fields<'a, C: Context<'a>>(cx: &mut C, obj: Handle<'a, JsObject>, path: &str, known: &[&str]) -> NeonResult<()> {
+  let keys = obj.get_own_property_names(cx)?;
+  for i in 0..keys.len() {
+    if let Ok(key) = keys.get(cx, i)?.downcast::<JsString>() {
+      let key = key.value();
+      if !known.contains(&key.as_str()) {
+        return cx.throw_type_error(format!("Unknown field {}, expected one of {}", field(path, &key), known.join(", ")));
+      }
+    }
+  }
+  Ok(())
+}
+
+/// Plain object at `path`
+pub fn object<'a, C: Context<'a>>(cx: &mut C, value: Handle<'a, JsValue>, path: &str) -> NeonResult<Handle<'a, JsObject>> {
+  if value.is_a::<JsArray>() || value.is_a::<JsFunction>() {
+    return expected(cx, path, "an object", value);
+  }
+  match value.downcast::<JsObject>() {
+    Ok(obj) => Ok(obj),
+    Err(_) => expected(cx, path, "an object", value)
+  }
+}
+
+/// Finite number at `path`
+pub fn number<'a, C: Context<'a>>(cx: &mut C, value: Handle<'a, JsValue>, path: &str) -> NeonResult<f32> {
+  match value.downcast::<JsNumber>() {
+    Ok(n) if n.value().is_finite() => Ok(n.value() as f32),
+    Ok(n) => cx.throw_range_error(format!("Expected {} to be finite, got {}", path, n.value())),
+    Err(_) => expected(cx, path, "a number", value)
+  }
+}
+
+/// Non-negative integer at `path`, e.g. a count or an index
+pub fn count<'a, C: Context<'a>>(cx: &mut C, value: Handle<'a, JsValue>, path: &str) -> NeonResult<usize> {
+  match value.downcast::<JsNumber>() {
+    Ok(n) if n.value() >= 0.0 && n.value().fract() == 0.0 && n.value() <= u32::MAX as f64 => Ok(n.value() as usize),
+    Ok(n) => cx.throw_range_error(format!("Expected {} to be a non-negative integer, got {}", path, n.value())),
+    Err(_) => expected(cx, path, "a number", value)
+  }
+}
+
+pub fn positive_count<'a, C: Context<'a>>(cx: &mut C, value: Handle<'a, JsValue>, path: &str) -> NeonResult<usize> {
+  match count(cx, value, path)? {
+    0 => cx.throw_range_error(format!("Expected {} to be positive, got 0", path)),
+    n => Ok(n)
+  }
+}
+
+pub fn number_or<'a, C: Context<'a>>(cx: &mut C, obj: Handle<'a, JsObject>, path: &str, key: &str, default: f32) -> NeonResult<f32> {
+  let value = obj.get(cx, key)?;
+  if is_absent(value) { Ok(default) } else { number(cx, value, &field(path, key)) }
+}
+
+/// Number that must be greater than zero, such as a radius
+pub fn positive_or<'a, C: Context<'a>>(cx: &mut C, obj: Handle<'a, JsObject>, path: &str, key: &str, default: f32) -> NeonResult<f32> {
+  let value = number_or(cx, obj, path, key, default)?;
+  if value > 0.0 {
+    Ok(value)
+  } else {
+    cx.throw_range_error(format!("Expected {} to be positive, got {}", field(path, key), value))
+  }
+}
+
+pub fn count_or<'a, C: Context<'a>>(cx: &mut C, obj: Handle<'a, JsObject>, path: &str, key: &str, default: usize) -> NeonResult<usize> {
+  let value = obj.get(cx, key)?;
+  if is_absent(value) { Ok(default) } else { count(cx, value, &field(path, key)) }
+}
+
+pub fn string_or<'a, C: Context<'a>>(cx: &mut C, obj: Handle<'a, JsObject>, path: &str, key: &str, default: &str) -> NeonResult<String> {
+  let value = obj.get(cx, key)?;
+  if is_absent(value) {
+    return Ok(String::from(default));
+  }
+  match value.downcast::<JsString>() {
+    Ok(s) => Ok(s.value()),
+    Err(_) => expected(cx, &field(path, key), "a string", value)
+  }
+}
+
+/// Size of an `ImageData`-like `{ width, height, data }` object
+pub fn image_dimension_from_js<'a, C: Context<'a>>(cx: &mut C, img_data: Handle<'a, JsObject>) -> NeonResult<ImageDimension> {
+  let width = img_data.get(cx, "width")?;
+  let width = positive_count(cx, width, "imgData.width")?;
+  let height = img_data.get(cx, "height")?;
+  let height = positive_count(cx, height, "imgData.height")?;
+  Ok(ImageDimension { width, height })
+}
+
+/// The `data` buffer of an image, checked to hold all of its RGBA pixels
+/// before anything gets written to it. It has to be a Node `Buffer`; the JS
+/// wrapper turns typed arrays such as a canvas `Uint8ClampedArray` into one.
+pub fn image_buffer_from_js<'a, C: Context<'a>>(cx: &mut C, img_data: Handle<'a, JsObject>, img_dim: &ImageDimension) -> NeonResult<Handle<'a, JsBuffer>> {
+  let data = img_data.get(cx, "data")?;
+  let buffer = match data.downcast::<JsBuffer>() {
+    Ok(buffer) => buffer,
+    Err(_) => return expected(cx, "imgData.data", "a Buffer (wrap typed arrays with Buffer.from(array.buffer))", data)
+  };
+  let length = cx.borrow(&buffer, |data| data.len());
+  let required = img_dim.width * img_dim.height * 4;
+  if length < required {
+    return cx.throw_range_error(format!(
+      "imgData.data holds {} bytes but {}x{} RGBA pixels need {}",
+      length, img_dim.width, img_dim.height, required
+    ));
+  }
+  Ok(buffer)
+}